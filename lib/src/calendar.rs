@@ -0,0 +1,169 @@
+use chrono::{DateTime, Utc};
+
+use crate::reschedule::Reschedule;
+use crate::summarize::FeedSummary;
+
+/// Lines longer than this (in bytes, not counting the line break) have to be
+/// folded onto the next.
+const MAX_LINE_LENGTH: usize = 75;
+
+/// An iCalendar (RFC 5545) feed with an event for each replayed item, along
+/// with any still to come (see `project_feed`). Those are marked as tentative
+/// since anything newly published could push them back.
+pub fn replay_calendar(
+    name: &str,
+    summary: &FeedSummary,
+    replayed: &Reschedule<String>,
+    upcoming: &Reschedule<String>,
+    now: DateTime<Utc>,
+) -> String {
+    let mut events: Vec<_> = summary
+        .items
+        .iter()
+        .filter_map(|item| {
+            let (slot, status) = match (replayed.get(&item.id), upcoming.get(&item.id)) {
+                (Some(slot), _) => (slot, "CONFIRMED"),
+                (None, Some(slot)) => (slot, "TENTATIVE"),
+                (None, None) => return None,
+            };
+            Some((slot, status, item))
+        })
+        .collect();
+    events.sort_by_key(|(slot, _, item)| (*slot, &item.id));
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//PodReplay//Replay Schedule//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        format!("X-WR-CALNAME:{}", escape_text(name)),
+    ];
+    for (slot, status, item) in events {
+        let mut description = format!(
+            "Originally published {}",
+            item.timestamp.format("%B %-d, %Y")
+        );
+        if let Some(link) = &item.link {
+            description.push_str(&format!("\n{link}"));
+        }
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}@podreplay", escape_text(&item.id)),
+            format!("DTSTAMP:{}", format_timestamp(&now)),
+            format!("DTSTART:{}", format_timestamp(slot)),
+            format!("SUMMARY:{}", escape_text(&item.title)),
+            format!("DESCRIPTION:{}", escape_text(&description)),
+            format!("STATUS:{status}"),
+        ]);
+        if let Some(link) = &item.link {
+            lines.push(format!("URL:{link}"));
+        }
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold_line(line) + "\r\n").collect()
+}
+
+fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Breaks up long lines without splitting any characters, continuing each
+/// with a leading space.
+fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len());
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_LENGTH {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{fold_line, replay_calendar};
+    use crate::{test_helpers::parse_dt, FeedSummary};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn replayed_and_upcoming() {
+        let xml = include_bytes!("../tests/data/podcasting20.xml");
+        let summary = FeedSummary::new("testing".into(), xml).unwrap();
+        let replayed = HashMap::from([(
+            "example-episode-1".to_string(),
+            parse_dt("2021-12-13T16:00:00"),
+        )]);
+        let upcoming = HashMap::from([(
+            "example-episode-2".to_string(),
+            parse_dt("2021-12-20T16:00:00"),
+        )]);
+        let output = replay_calendar(
+            "Podcasting 2.0, replayed",
+            &summary,
+            &replayed,
+            &upcoming,
+            parse_dt("2021-12-14T12:00:00"),
+        );
+        let expected = [
+            "BEGIN:VCALENDAR",
+            "VERSION:2.0",
+            "PRODID:-//PodReplay//Replay Schedule//EN",
+            "CALSCALE:GREGORIAN",
+            "X-WR-CALNAME:Podcasting 2.0\\, replayed",
+            "BEGIN:VEVENT",
+            "UID:example-episode-1@podreplay",
+            "DTSTAMP:20211214T120000Z",
+            "DTSTART:20211213T160000Z",
+            "SUMMARY:Episode 1",
+            "DESCRIPTION:Originally published October 2\\, 2020",
+            "STATUS:CONFIRMED",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "UID:example-episode-2@podreplay",
+            "DTSTAMP:20211214T120000Z",
+            "DTSTART:20211220T160000Z",
+            "SUMMARY:Episode 2",
+            "DESCRIPTION:Originally published October 9\\, 2020\\nhttps://example.com/epis",
+            " ode2",
+            "STATUS:TENTATIVE",
+            "URL:https://example.com/episode2",
+            "END:VEVENT",
+            "END:VCALENDAR",
+            "",
+        ]
+        .join("\r\n");
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn folds_long_lines() {
+        let line = format!("SUMMARY:{}", "é".repeat(40));
+        let folded = fold_line(&line);
+        assert!(folded.split("\r\n").all(|line| line.len() <= 75));
+        assert_eq!(folded.replace("\r\n ", ""), line);
+    }
+}
@@ -68,13 +68,18 @@ mod test {
                 id: id.to_string(),
                 title: id.to_string(),
                 timestamp: parse_dt(timestamp),
+                ..Default::default()
             })
             .collect();
         FeedSummary {
             uri: "testing".into(),
             title: "testing".into(),
             marked_private: true,
+            podcast_guid: None,
+            locked: false,
+            new_feed_url: None,
             items,
+            repairs: vec![],
         }
     }
 
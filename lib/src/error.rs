@@ -0,0 +1,160 @@
+use quick_xml::events::Event;
+use serde::Serialize;
+use std::collections::HashMap;
+use thiserror::Error;
+
+const SNIPPET_RADIUS: usize = 40;
+
+/// A feed parsing failure along with enough context (line/column, the path to
+/// the element we were in and a bit of the surrounding text) for someone to
+/// track down the problem in the original document.
+#[derive(Error, Debug, Serialize)]
+#[error("{message} at line {line}, column {column} ({path})")]
+pub struct ParseError {
+    pub message: String,
+    pub position: usize,
+    pub line: usize,
+    pub column: usize,
+    pub path: String,
+    pub snippet: String,
+    #[serde(skip)]
+    pub source: quick_xml::Error,
+}
+
+impl ParseError {
+    /// An error we only know the position of so far, since we were reading
+    /// from a stream rather than a document we can look back through.
+    pub(crate) fn at(position: usize, source: quick_xml::Error) -> Self {
+        ParseError {
+            message: source.to_string(),
+            position,
+            line: 0,
+            column: 0,
+            path: String::new(),
+            snippet: String::new(),
+            source,
+        }
+    }
+
+    /// Fills in the line, column, path and snippet by looking at the document
+    /// the error came from.
+    pub fn with_context(self, xml: &[u8]) -> Self {
+        let position = self.position.min(xml.len());
+        let consumed = &xml[..position];
+        let line_start = consumed
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |index| index + 1);
+        let line_end = xml[position..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(xml.len(), |index| position + index);
+        let column = String::from_utf8_lossy(&consumed[line_start..])
+            .chars()
+            .count();
+
+        ParseError {
+            position,
+            line: consumed.iter().filter(|b| **b == b'\n').count() + 1,
+            column: column + 1,
+            path: element_path(consumed),
+            snippet: snippet(&xml[line_start..line_end], column),
+            ..self
+        }
+    }
+
+    /// Whether the error happened somewhere inside what looks like a feed, as
+    /// opposed to some other document (an HTML page, for example) that was
+    /// never going to parse.
+    pub fn is_in_feed(&self) -> bool {
+        let root = self.path.split('/').next().unwrap_or_default();
+        matches!(root, "rss" | "feed" | "rdf:RDF")
+    }
+}
+
+/// Re-reads everything up to the error to reconstruct where we were, e.g.
+/// `rss/channel/item[12]/pubDate`. Items and entries are numbered since they're
+/// the only repeated elements anyone will need to find.
+fn element_path(consumed: &[u8]) -> String {
+    let mut reader = quick_xml::Reader::from_reader(consumed);
+    reader.check_end_names(false);
+    let mut buf = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+    let mut child_counts: Vec<HashMap<String, usize>> = vec![HashMap::new()];
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(start)) => {
+                let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+                let count = child_counts
+                    .last_mut()
+                    .map(|counts| {
+                        let count = counts.entry(name.clone()).or_default();
+                        *count += 1;
+                        *count
+                    })
+                    .unwrap_or(1);
+                path.push(match name.as_str() {
+                    "item" | "entry" => format!("{name}[{count}]"),
+                    _ => name,
+                });
+                child_counts.push(HashMap::new());
+            }
+            Ok(Event::End(_)) => {
+                // A closing tag that runs right up to the error is most likely
+                // what the error is about, so we're still "inside" it.
+                if reader.buffer_position() >= consumed.len() {
+                    break;
+                }
+                path.pop();
+                if child_counts.len() > 1 {
+                    child_counts.pop();
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            Ok(_) => {}
+        }
+        buf.clear();
+    }
+    path.join("/")
+}
+
+fn snippet(line: &[u8], column: usize) -> String {
+    let chars: Vec<char> = String::from_utf8_lossy(line).chars().collect();
+    let end = (column + SNIPPET_RADIUS).min(chars.len());
+    let start = column.saturating_sub(SNIPPET_RADIUS).min(end);
+    chars[start..end]
+        .iter()
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::ParseError;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn locates_errors() {
+        let xml = "<rss>\n  <channel>\n    <item><title>One</title></item>\n    <item>\n      <pubDate>Mon, 30 Sep 2002</pubdate>\n    </item>\n  </channel>\n</rss>";
+        let position = xml.find("</pubdate>").unwrap();
+        let source = quick_xml::Error::TextNotFound;
+        let error = ParseError::at(position, source).with_context(xml.as_bytes());
+        assert_eq!(error.line, 5);
+        assert_eq!(error.column, 32);
+        assert_eq!(error.path, "rss/channel/item[2]/pubDate");
+        assert_eq!(error.snippet, "<pubDate>Mon, 30 Sep 2002</pubdate>");
+        assert!(error.is_in_feed());
+    }
+
+    #[test]
+    fn clamps_positions_past_the_end() {
+        let xml = "<html><body>";
+        let error =
+            ParseError::at(100, quick_xml::Error::TextNotFound).with_context(xml.as_bytes());
+        assert_eq!(error.line, 1);
+        assert_eq!(error.column, 13);
+        assert_eq!(error.path, "html/body");
+        assert!(!error.is_in_feed());
+    }
+}
@@ -1,18 +1,35 @@
+mod calendar;
 mod diff;
+mod error;
+mod plan;
+mod repair;
 mod reschedule;
 mod rewrite;
 mod rule;
+mod status;
 mod summarize;
+mod template;
 
 #[cfg(test)]
 pub mod test_helpers;
 
+pub use calendar::replay_calendar;
 use chrono::{DateTime, Utc};
 pub use diff::{create_cached_entry_map, diff_feed};
-pub use reschedule::{reschedule_feed, Item, Reschedule};
-pub use rewrite::{rewrite_feed, RewriteError};
+pub use error::ParseError;
+pub use plan::{plan_feed, Plan, PlannedItem};
+pub use repair::{Repair, RepairKind};
+pub use reschedule::{project_feed, reschedule_feed, Item, Reschedule};
+pub use rewrite::{
+    rewrite_feed, rewrite_feed_lenient, rewrite_feed_to_writer, FeedOverrides, NotePosition,
+    RewriteError, RewriteOptions,
+};
 pub use rule::{parse_rule, Rule};
-pub use summarize::{parse_timestamp, FeedSummary, SummarizeError, SummaryItem};
+pub use status::ReplayStatus;
+pub use summarize::{
+    parse_timestamp, Chapters, FeedSummary, SummarizeError, SummaryItem, Transcript,
+};
+pub use template::{TemplateError, TitleTemplate};
 
 #[derive(Debug)]
 pub struct FeedMeta {
@@ -21,6 +38,7 @@ pub struct FeedMeta {
     pub first_fetched: DateTime<Utc>,
     pub last_fetched: DateTime<Utc>,
     pub etag: Option<String>,
+    pub last_modified: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -0,0 +1,117 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::reschedule::{project_feed, Item, Key, Reschedule};
+use crate::rule::Rule;
+
+/// Every item in a replay, in the order they come out. Anything marked as
+/// `projected` assumes nothing else is published in the meantime, so it's
+/// subject to change whenever the feed does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Plan<K> {
+    pub items: Vec<PlannedItem<K>>,
+    pub next_slot: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedItem<K> {
+    pub id: K,
+    pub date: DateTime<Utc>,
+    pub projected: bool,
+}
+
+impl<K: Key + Ord> Plan<K> {
+    /// Takes what `project_feed` returned.
+    pub fn new(
+        replayed: Reschedule<K>,
+        next_slot: Option<DateTime<Utc>>,
+        upcoming: Reschedule<K>,
+    ) -> Self {
+        let planned = |projected| {
+            move |(id, date)| PlannedItem {
+                id,
+                date,
+                projected,
+            }
+        };
+        let mut items: Vec<_> = replayed
+            .into_iter()
+            .map(planned(false))
+            .chain(upcoming.into_iter().map(planned(true)))
+            .collect();
+        items.sort_by(|a, b| (a.date, &a.id).cmp(&(b.date, &b.id)));
+        Plan { items, next_slot }
+    }
+}
+
+/// Runs the schedule past `cutoff` to see when every item will be replayed.
+/// See `reschedule_feed` for the rest of the arguments.
+pub fn plan_feed<K, I, FeedNoticed, FirstItem, LastItem>(
+    items: &[I],
+    rule: Rule,
+    start: DateTime<Utc>,
+    cutoff: DateTime<Utc>,
+    feed_noticed: FeedNoticed,
+    first_item: FirstItem,
+    last_item: LastItem,
+) -> Plan<K>
+where
+    K: Key + Ord,
+    I: Item<K>,
+    FeedNoticed: Into<Option<DateTime<Utc>>>,
+    FirstItem: Into<Option<DateTime<Utc>>>,
+    LastItem: Into<Option<DateTime<Utc>>>,
+{
+    let (replayed, next_slot, upcoming) = project_feed(
+        items,
+        rule,
+        start,
+        cutoff,
+        feed_noticed,
+        first_item,
+        last_item,
+    );
+    Plan::new(replayed, next_slot, upcoming)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{plan_feed, PlannedItem};
+    use crate::parse_rule;
+    use crate::test_helpers::{cached_entries, parse_dt};
+
+    #[test]
+    fn orders_replayed_then_projected() {
+        let items = cached_entries(
+            1,
+            vec![
+                ("1", "2013-11-28T21:00:00", "pub"),
+                ("2", "2013-12-04T21:00:00", "pub"),
+                ("3", "2013-12-11T21:00:00", "pub"),
+            ],
+        );
+        let plan = plan_feed(
+            &items,
+            parse_rule(parse_dt("2014-11-28T21:00:00"), "1w"),
+            parse_dt("2014-11-28T21:00:00"),
+            parse_dt("2014-12-06T12:00:00"),
+            parse_dt("2014-11-28T21:00:00"),
+            None,
+            None,
+        );
+        let planned = |id: &str, date, projected| PlannedItem {
+            id: id.to_string(),
+            date: parse_dt(date),
+            projected,
+        };
+        assert_eq!(
+            plan.items,
+            vec![
+                planned("1", "2014-11-28T21:00:00", false),
+                planned("2", "2014-12-05T21:00:00", false),
+                planned("3", "2014-12-12T21:00:00", true),
+            ]
+        );
+        assert_eq!(plan.next_slot, Some(parse_dt("2014-12-12T21:00:00")));
+    }
+}
@@ -0,0 +1,516 @@
+use std::borrow::Cow;
+
+use quick_xml::{events::Event, Reader};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ParseError;
+
+/// Something we had to fix or throw away to get a malformed feed through in
+/// lenient mode.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Repair {
+    pub kind: RepairKind,
+    pub line: usize,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepairKind {
+    EscapedAmpersand,
+    ReplacedEntity,
+    WrappedInCdata,
+    SkippedItem,
+    TruncatedDocument,
+}
+
+impl Repair {
+    pub(crate) fn from_error(kind: RepairKind, err: &ParseError) -> Self {
+        Repair {
+            kind,
+            line: err.line,
+            detail: err.to_string(),
+        }
+    }
+}
+
+/// How many broken items we'll cut out of a feed before deciding it's beyond
+/// saving. Each one means another full parse, so this can't be unbounded.
+const MAX_SKIPPED_ITEMS: usize = 100;
+
+/// Implemented by the errors lenient parsing knows how to recover from.
+pub(crate) trait Recoverable {
+    fn parse_error(&self) -> Option<&ParseError>;
+}
+
+/// Runs `attempt` over a repaired copy of the feed. If it trips over a broken
+/// item, we cut that item out and try again until it succeeds (or fails in a
+/// way we can't fix).
+pub(crate) fn with_recovery<T, E: Recoverable>(
+    xml: &[u8],
+    mut attempt: impl FnMut(&[u8]) -> Result<T, E>,
+) -> Result<(T, Vec<Repair>), E> {
+    let (mut xml, mut repairs) = repair_feed(xml);
+    for _ in 0..MAX_SKIPPED_ITEMS {
+        let err = match attempt(&xml) {
+            Ok(output) => return Ok((output, repairs)),
+            Err(err) => err,
+        };
+        match err.parse_error().and_then(|err| cut_broken_item(&xml, err)) {
+            Some((cut, repair)) => {
+                repairs.push(repair);
+                xml = Cow::Owned(cut);
+            }
+            None => return Err(err),
+        }
+    }
+    attempt(&xml).map(|output| (output, repairs))
+}
+
+/// Removes the item an error happened in, from its start tag up to the start
+/// of whatever comes after it. If nothing does, the feed was cut off partway
+/// through the item, so we close off everything it was nested in instead.
+fn cut_broken_item(xml: &[u8], error: &ParseError) -> Option<(Vec<u8>, Repair)> {
+    let in_item = error
+        .path
+        .split('/')
+        .any(|name| name.starts_with("item[") || name.starts_with("entry["));
+    if !in_item {
+        return None;
+    }
+    let position = error.position.min(xml.len());
+    let start = rfind_tag(&xml[..position], &["item", "entry"])?;
+    let next = find_tag(
+        &xml[position..],
+        &["item", "entry", "/channel", "/feed", "/rdf:RDF"],
+    );
+
+    let mut output = xml[..start].to_vec();
+    let kind = match next {
+        Some(index) => {
+            output.extend_from_slice(&xml[position + index..]);
+            RepairKind::SkippedItem
+        }
+        None => {
+            for name in ancestors(&error.path).iter().rev() {
+                output.extend_from_slice(format!("</{name}>").as_bytes());
+            }
+            RepairKind::TruncatedDocument
+        }
+    };
+    Some((output, Repair::from_error(kind, error)))
+}
+
+/// The elements an item is nested in, given the path to something inside it.
+fn ancestors(path: &str) -> Vec<&str> {
+    path.split('/')
+        .map(|name| name.split('[').next().unwrap_or(name))
+        .take_while(|name| !matches!(*name, "item" | "entry"))
+        .collect()
+}
+
+fn is_tag(haystack: &[u8], index: usize, names: &[&str]) -> bool {
+    haystack[index] == b'<'
+        && names.iter().any(|name| {
+            let rest = &haystack[index + 1..];
+            rest.starts_with(name.as_bytes())
+                && rest.get(name.len()).map_or(false, |b| {
+                    b.is_ascii_whitespace() || matches!(b, b'>' | b'/')
+                })
+        })
+}
+
+fn rfind_tag(haystack: &[u8], names: &[&str]) -> Option<usize> {
+    (0..haystack.len())
+        .rev()
+        .find(|&index| is_tag(haystack, index, names))
+}
+
+/// Finds the first `<name` tag for any of the given names, making sure it's
+/// not just the start of a longer name.
+fn find_tag(haystack: &[u8], names: &[&str]) -> Option<usize> {
+    (0..haystack.len()).find(|&index| is_tag(haystack, index, names))
+}
+
+/// Repairs what we can before parsing: text elements full of HTML that
+/// doesn't balance get wrapped in CDATA, then any entities are fixed up.
+fn repair_feed(xml: &[u8]) -> (Cow<'_, [u8]>, Vec<Repair>) {
+    let (wrapped, mut repairs) = wrap_unbalanced_html(xml);
+    let (repaired, entity_repairs) = repair_entities(&wrapped);
+    repairs.extend(entity_repairs);
+    let repaired = match repaired {
+        Cow::Owned(repaired) => Cow::Owned(repaired),
+        Cow::Borrowed(_) => wrapped.clone(),
+    };
+    (repaired, repairs)
+}
+
+/// Elements that commonly contain HTML, which people often forget to escape.
+const HTML_ELEMENTS: [&str; 5] = [
+    "description",
+    "content:encoded",
+    "itunes:summary",
+    "summary",
+    "content",
+];
+
+/// Wraps the contents of any HTML-bearing element whose markup doesn't
+/// balance (`<p>Hello<br></p>`, for example) in CDATA so it's just text.
+fn wrap_unbalanced_html(xml: &[u8]) -> (Cow<'_, [u8]>, Vec<Repair>) {
+    let mut repairs = Vec::new();
+    let mut output: Option<Vec<u8>> = None;
+    let mut copied = 0;
+    let mut index = 0;
+
+    while let Some(found) = find_tag(&xml[index..], &HTML_ELEMENTS) {
+        let start = index + found;
+        let name_end = start
+            + 1
+            + xml[start + 1..]
+                .iter()
+                .position(|b| b.is_ascii_whitespace() || matches!(b, b'>' | b'/'))
+                .unwrap_or(0);
+        let name = &xml[start + 1..name_end];
+        let Some(tag_end) = xml[name_end..].iter().position(|b| *b == b'>') else {
+            break;
+        };
+        let content_start = name_end + tag_end + 1;
+        index = content_start;
+        if xml[content_start - 2] == b'/' {
+            continue;
+        }
+        let close = [b"</", name, b">"].concat();
+        let Some(content_len) = find(&xml[content_start..], &close) else {
+            continue;
+        };
+        let content = &xml[content_start..content_start + content_len];
+        index = content_start + content_len + close.len();
+        // If we'd be swallowing other items, the problem is something else and
+        // we're better off leaving it for the parser to trip over.
+        if find_tag(content, &["item", "/item", "entry", "/entry"]).is_some()
+            || is_balanced(content)
+        {
+            continue;
+        }
+
+        let out = output.get_or_insert_with(|| Vec::with_capacity(xml.len()));
+        out.extend_from_slice(&xml[copied..content_start]);
+        out.extend_from_slice(b"<![CDATA[");
+        out.extend_from_slice(&replace(content, b"]]>", b"]]]]><![CDATA[>"));
+        out.extend_from_slice(b"]]>");
+        copied = content_start + content_len;
+        repairs.push(Repair {
+            kind: RepairKind::WrappedInCdata,
+            line: count_lines(&xml[..content_start]) + 1,
+            detail: format!(
+                "Wrapped unbalanced markup in <{}> in CDATA",
+                String::from_utf8_lossy(name)
+            ),
+        });
+    }
+
+    match output {
+        Some(mut out) => {
+            out.extend_from_slice(&xml[copied..]);
+            (Cow::Owned(out), repairs)
+        }
+        None => (Cow::Borrowed(xml), repairs),
+    }
+}
+
+fn is_balanced(content: &[u8]) -> bool {
+    if !content.contains(&b'<') {
+        return true;
+    }
+    let mut reader = Reader::from_reader(content);
+    let mut buf = Vec::new();
+    let mut depth = 0;
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(_)) => depth += 1,
+            Ok(Event::End(_)) => depth -= 1,
+            Ok(Event::Eof) => return depth == 0,
+            Err(_) => return false,
+            Ok(_) => {}
+        }
+        buf.clear();
+    }
+}
+
+fn replace(haystack: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(haystack.len());
+    let mut rest = haystack;
+    while let Some(index) = find(rest, from) {
+        output.extend_from_slice(&rest[..index]);
+        output.extend_from_slice(to);
+        rest = &rest[index + from.len()..];
+    }
+    output.extend_from_slice(rest);
+    output
+}
+
+/// Fixes up the most common ways feeds break at the character level: bare
+/// ampersands and HTML-only named entities like `&nbsp;`. CDATA sections and
+/// comments are left alone since neither is parsed for entities anyway.
+fn repair_entities(xml: &[u8]) -> (Cow<'_, [u8]>, Vec<Repair>) {
+    let mut repairs = Vec::new();
+    let mut output: Option<Vec<u8>> = None;
+    let mut line = 1;
+    let mut index = 0;
+    let mut copied = 0;
+
+    while index < xml.len() {
+        let rest = &xml[index..];
+        if let Some(skip) = [(&b"<![CDATA["[..], &b"]]>"[..]), (b"<!--", b"-->")]
+            .iter()
+            .find(|(open, _)| rest.starts_with(open))
+            .map(|(open, close)| {
+                find(&rest[open.len()..], close).map(|i| open.len() + i + close.len())
+            })
+        {
+            let skip = skip.unwrap_or(rest.len());
+            line += count_lines(&rest[..skip]);
+            index += skip;
+            continue;
+        }
+
+        match xml[index] {
+            b'\n' => line += 1,
+            b'&' => {
+                if let Some((replacement, kind)) = repair_entity(rest) {
+                    let out = output.get_or_insert_with(|| Vec::with_capacity(xml.len()));
+                    out.extend_from_slice(&xml[copied..index]);
+                    out.extend_from_slice(replacement.as_bytes());
+                    // A replaced entity swallows its whole name, whereas a bare
+                    // ampersand only swaps out the `&` itself.
+                    let replaced = match kind {
+                        RepairKind::ReplacedEntity => entity_name(rest),
+                        _ => &rest[..1],
+                    };
+                    repairs.push(Repair {
+                        kind,
+                        line,
+                        detail: format!(
+                            "Replaced {} with {}",
+                            String::from_utf8_lossy(replaced),
+                            replacement
+                        ),
+                    });
+                    index += replaced.len();
+                    copied = index;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+
+    match output {
+        Some(mut out) => {
+            out.extend_from_slice(&xml[copied..]);
+            (Cow::Owned(out), repairs)
+        }
+        None => (Cow::Borrowed(xml), repairs),
+    }
+}
+
+/// Given input starting with `&`, returns what to replace the entity with (or
+/// just the ampersand) if it needs repairing.
+fn repair_entity(rest: &[u8]) -> Option<(String, RepairKind)> {
+    let name = entity_name(rest);
+    if name.len() < 3 || !name.ends_with(b";") {
+        return Some(("&amp;".to_string(), RepairKind::EscapedAmpersand));
+    }
+    let name = &name[1..name.len() - 1];
+    if name.starts_with(b"#") {
+        let valid = match &name[1..] {
+            [b'x', hex @ ..] => !hex.is_empty() && hex.iter().all(u8::is_ascii_hexdigit),
+            digits => !digits.is_empty() && digits.iter().all(u8::is_ascii_digit),
+        };
+        return (!valid).then(|| ("&amp;".to_string(), RepairKind::EscapedAmpersand));
+    }
+    if matches!(name, b"amp" | b"lt" | b"gt" | b"quot" | b"apos") {
+        return None;
+    }
+    match html_entity(name) {
+        Some(code) => Some((format!("&#{code};"), RepairKind::ReplacedEntity)),
+        None => Some(("&amp;".to_string(), RepairKind::EscapedAmpersand)),
+    }
+}
+
+/// The `&...;` run at the start of `rest`, or just as much of it as looks like
+/// an entity if there's no terminating semicolon.
+fn entity_name(rest: &[u8]) -> &[u8] {
+    let len = rest
+        .iter()
+        .skip(1)
+        .take(32)
+        .position(|b| !(b.is_ascii_alphanumeric() || *b == b'#'))
+        .map_or(rest.len().min(33), |i| i + 1);
+    if rest.get(len) == Some(&b';') {
+        &rest[..=len]
+    } else {
+        &rest[..len]
+    }
+}
+
+fn html_entity(name: &[u8]) -> Option<u32> {
+    LATIN1_ENTITIES
+        .iter()
+        .position(|entity| entity.as_bytes() == name)
+        .map(|index| index as u32 + 160)
+        .or_else(|| {
+            OTHER_ENTITIES
+                .iter()
+                .find(|(entity, _)| entity.as_bytes() == name)
+                .map(|(_, code)| *code)
+        })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn count_lines(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|b| **b == b'\n').count()
+}
+
+/// HTML's named entities for U+00A0 through U+00FF, in order.
+const LATIN1_ENTITIES: [&str; 96] = [
+    "nbsp", "iexcl", "cent", "pound", "curren", "yen", "brvbar", "sect", "uml", "copy", "ordf",
+    "laquo", "not", "shy", "reg", "macr", "deg", "plusmn", "sup2", "sup3", "acute", "micro",
+    "para", "middot", "cedil", "sup1", "ordm", "raquo", "frac14", "frac12", "frac34", "iquest",
+    "Agrave", "Aacute", "Acirc", "Atilde", "Auml", "Aring", "AElig", "Ccedil", "Egrave", "Eacute",
+    "Ecirc", "Euml", "Igrave", "Iacute", "Icirc", "Iuml", "ETH", "Ntilde", "Ograve", "Oacute",
+    "Ocirc", "Otilde", "Ouml", "times", "Oslash", "Ugrave", "Uacute", "Ucirc", "Uuml", "Yacute",
+    "THORN", "szlig", "agrave", "aacute", "acirc", "atilde", "auml", "aring", "aelig", "ccedil",
+    "egrave", "eacute", "ecirc", "euml", "igrave", "iacute", "icirc", "iuml", "eth", "ntilde",
+    "ograve", "oacute", "ocirc", "otilde", "ouml", "divide", "oslash", "ugrave", "uacute", "ucirc",
+    "uuml", "yacute", "thorn", "yuml",
+];
+
+/// The rest of the HTML entities that show up in show notes with any regularity.
+const OTHER_ENTITIES: &[(&str, u32)] = &[
+    ("OElig", 338),
+    ("oelig", 339),
+    ("Scaron", 352),
+    ("scaron", 353),
+    ("Yuml", 376),
+    ("fnof", 402),
+    ("circ", 710),
+    ("tilde", 732),
+    ("ensp", 8194),
+    ("emsp", 8195),
+    ("thinsp", 8201),
+    ("zwnj", 8204),
+    ("zwj", 8205),
+    ("lrm", 8206),
+    ("rlm", 8207),
+    ("ndash", 8211),
+    ("mdash", 8212),
+    ("lsquo", 8216),
+    ("rsquo", 8217),
+    ("sbquo", 8218),
+    ("ldquo", 8220),
+    ("rdquo", 8221),
+    ("bdquo", 8222),
+    ("dagger", 8224),
+    ("Dagger", 8225),
+    ("bull", 8226),
+    ("hellip", 8230),
+    ("permil", 8240),
+    ("prime", 8242),
+    ("Prime", 8243),
+    ("lsaquo", 8249),
+    ("rsaquo", 8250),
+    ("euro", 8364),
+    ("trade", 8482),
+    ("larr", 8592),
+    ("uarr", 8593),
+    ("rarr", 8594),
+    ("darr", 8595),
+    ("harr", 8596),
+];
+
+#[cfg(test)]
+mod test {
+    use super::{ancestors, repair_entities, repair_feed, RepairKind};
+    use pretty_assertions::assert_eq;
+
+    fn repaired(xml: &str) -> (String, Vec<RepairKind>) {
+        let (output, repairs) = repair_entities(xml.as_bytes());
+        (
+            String::from_utf8(output.into_owned()).unwrap(),
+            repairs.into_iter().map(|r| r.kind).collect(),
+        )
+    }
+
+    #[test]
+    fn leaves_valid_xml_alone() {
+        let xml = "<a href=\"?a=1&amp;b=2\">&lt;&#160;&#xA0;&quot;</a>";
+        assert_eq!(repaired(xml), (xml.to_string(), vec![]));
+    }
+
+    #[test]
+    fn escapes_bare_ampersands() {
+        assert_eq!(
+            repaired("<a href=\"?a=1&b=2\">Tom & Jerry &#zz; &#; &#x; &</a>"),
+            (
+                "<a href=\"?a=1&amp;b=2\">Tom &amp; Jerry &amp;#zz; &amp;#; &amp;#x; &amp;</a>"
+                    .to_string(),
+                vec![RepairKind::EscapedAmpersand; 6]
+            )
+        );
+    }
+
+    #[test]
+    fn replaces_html_entities() {
+        assert_eq!(
+            repaired("<p>Caf&eacute;&nbsp;&hellip; &bogus;</p>"),
+            (
+                "<p>Caf&#233;&#160;&#8230; &amp;bogus;</p>".to_string(),
+                vec![
+                    RepairKind::ReplacedEntity,
+                    RepairKind::ReplacedEntity,
+                    RepairKind::ReplacedEntity,
+                    RepairKind::EscapedAmpersand
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn ignores_cdata_and_comments() {
+        let xml = "<a><![CDATA[Tom & Jerry]]><!-- & --></a>\n<b>&</b>";
+        let (output, repairs) = repair_entities(xml.as_bytes());
+        assert_eq!(
+            String::from_utf8(output.into_owned()).unwrap(),
+            "<a><![CDATA[Tom & Jerry]]><!-- & --></a>\n<b>&amp;</b>"
+        );
+        assert_eq!(repairs[0].line, 2);
+    }
+
+    #[test]
+    fn wraps_unbalanced_html() {
+        let xml = "<item><description><p>Hi<br></p> & bye</description><title>Fine</title></item>\n<item><description>Fine <b>too</b></description></item>";
+        let (output, repairs) = repair_feed(xml.as_bytes());
+        assert_eq!(
+            String::from_utf8(output.into_owned()).unwrap(),
+            "<item><description><![CDATA[<p>Hi<br></p> & bye]]></description><title>Fine</title></item>\n<item><description>Fine <b>too</b></description></item>"
+        );
+        let repairs: Vec<_> = repairs.into_iter().map(|r| r.kind).collect();
+        assert_eq!(repairs, vec![RepairKind::WrappedInCdata]);
+    }
+
+    #[test]
+    fn finds_ancestors() {
+        assert_eq!(
+            ancestors("rss/channel/item[2]/guid"),
+            vec!["rss", "channel"]
+        );
+        assert_eq!(ancestors("feed/entry[1]"), vec!["feed"]);
+    }
+}
@@ -31,10 +31,58 @@ where
     FirstItem: Into<Option<DateTime<Utc>>>,
     LastItem: Into<Option<DateTime<Utc>>>,
 {
-    let cutoff = cutoff.into();
-    let feed_noticed = feed_noticed.into().unwrap_or(start);
-    let first_item = first_item.into();
-    let last_item = last_item.into();
+    let (results, next_slot, _) = reschedule(
+        items,
+        rule,
+        start,
+        cutoff.into(),
+        feed_noticed.into(),
+        (first_item.into(), last_item.into()),
+        false,
+    );
+    (results, next_slot)
+}
+
+/// Like `reschedule_feed`, but carries on past the cutoff to work out when
+/// the rest of the items would be replayed if nothing new were published.
+/// Those are returned separately, after the next slot.
+pub fn project_feed<K, I, FeedNoticed, FirstItem, LastItem>(
+    items: &[I],
+    rule: Rule,
+    start: DateTime<Utc>,
+    cutoff: DateTime<Utc>,
+    feed_noticed: FeedNoticed,
+    first_item: FirstItem,
+    last_item: LastItem,
+) -> (Reschedule<K>, Option<DateTime<Utc>>, Reschedule<K>)
+where
+    K: Key,
+    I: Item<K>,
+    FeedNoticed: Into<Option<DateTime<Utc>>>,
+    FirstItem: Into<Option<DateTime<Utc>>>,
+    LastItem: Into<Option<DateTime<Utc>>>,
+{
+    reschedule(
+        items,
+        rule,
+        start,
+        Some(cutoff),
+        feed_noticed.into(),
+        (first_item.into(), last_item.into()),
+        true,
+    )
+}
+
+fn reschedule<K: Key, I: Item<K>>(
+    items: &[I],
+    rule: Rule,
+    start: DateTime<Utc>,
+    cutoff: Option<DateTime<Utc>>,
+    feed_noticed: Option<DateTime<Utc>>,
+    (first_item, last_item): (Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+    project: bool,
+) -> (Reschedule<K>, Option<DateTime<Utc>>, Reschedule<K>) {
+    let feed_noticed = feed_noticed.unwrap_or(start);
 
     let mut item_iter = items.iter().filter(move |item| {
         if let Some(published) = item.published() {
@@ -48,11 +96,21 @@ where
     let mut instances_by_id = create_instances_by_id(items);
     let mut delayed = DelayedItems::new();
     let mut results = HashMap::new();
+    let mut next_slot = None;
+    let mut projected = HashMap::new();
 
-    for slot in rule {
+    'slots: for slot in rule {
         if matches!(cutoff, Some(cutoff) if slot >= cutoff) {
-            return (results, Some(slot));
+            if !project {
+                return (results, Some(slot), projected);
+            }
+            next_slot.get_or_insert(slot);
         }
+        // Anything past the cutoff is only a guess at what will happen.
+        let replayed = match next_slot {
+            Some(_) => &mut projected,
+            None => &mut results,
+        };
         let some_slot = Some(slot);
         loop {
             let next_item = delayed.pop_eligible(slot).or_else(|| item_iter.next());
@@ -77,7 +135,7 @@ where
                                 break; // we've already replayed this item here, so we need to keep the slot empty
                             }
                             Unpublished::Never => {
-                                results.insert(item.id().clone(), slot);
+                                replayed.insert(item.id().clone(), slot);
                                 instances.already_replayed = true;
                                 break; // slot filled, move to the next
                             }
@@ -85,18 +143,18 @@ where
                     } else if let Some(published) = item.published() {
                         // This was published after this slot, meaning we've apparently caught up.
                         // Keep replaying items at their original publication times.
-                        results.insert(item.id().clone(), published);
+                        replayed.insert(item.id().clone(), published);
                         instances.already_replayed = true;
                     }
                 }
             } else if delayed.is_empty() {
-                return (results, None); // ran out of items, don't loop over the rest of the slots
+                break 'slots; // ran out of items, don't loop over the rest of the slots
             } else {
                 break; // no eligible items available for this slot, try the next
             }
         }
     }
-    (results, None)
+    (results, next_slot, projected)
 }
 
 fn create_instances_by_id<K: Key, I: Item<K>>(items: &[I]) -> HashMap<&K, Scheduled<K, I>> {
@@ -183,7 +241,7 @@ mod test {
     use std::collections::HashMap;
 
     use crate::test_helpers::{cached_entries, parse_dt};
-    use crate::{parse_rule, reschedule_feed, Reschedule};
+    use crate::{parse_rule, project_feed, reschedule_feed, Reschedule};
 
     fn replayed_items<'a>(items: Vec<(&'a str, &'a str)>) -> Reschedule<String> {
         items
@@ -747,4 +805,38 @@ mod test {
             )
         );
     }
+
+    #[test]
+    fn projects_the_rest_of_the_items() {
+        let items = cached_entries(
+            1,
+            vec![
+                ("1", "2013-11-28T21:00:00", "pub"),
+                ("2", "2013-12-04T21:00:00", "pub"),
+                ("3", "2013-12-11T21:00:00", "pub"),
+                // Published after the cutoff, so it's not known about yet.
+                ("4", "2014-11-30T21:00:00", "pub"),
+            ],
+        );
+        let result = project_feed(
+            &items,
+            parse_rule(parse_dt("2014-11-28T21:00:00"), "1w"),
+            parse_dt("2014-11-28T21:00:00"),
+            parse_dt("2014-11-28T22:00:00"),
+            parse_dt("2014-11-28T22:00:00"),
+            None,
+            None,
+        );
+        assert_eq!(
+            result,
+            (
+                replayed_items(vec![("1", "2014-11-28T21:00:00")]),
+                Some(parse_dt("2014-12-05T21:00:00")),
+                replayed_items(vec![
+                    ("2", "2014-12-05T21:00:00"),
+                    ("3", "2014-12-12T21:00:00"),
+                ]),
+            )
+        );
+    }
 }
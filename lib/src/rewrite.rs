@@ -1,116 +1,659 @@
+use crate::error::ParseError;
+use crate::repair::{with_recovery, Recoverable, Repair};
 use crate::reschedule::Reschedule;
-use crate::summarize::{is_audio_enclosure, read_contents};
+use crate::status::ReplayStatus;
+use crate::summarize::{is_audio_enclosure, parse_timestamp, read_contents_into, read_raw_text};
 use chrono::{DateTime, SecondsFormat, Utc};
-use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::escape::partial_escape;
+use quick_xml::events::{BytesCData, BytesEnd, BytesStart, BytesText, Event};
 use quick_xml::name::QName;
 use quick_xml::{Reader, Writer};
-use std::io::{BufRead, Write};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+use std::ops::RangeInclusive;
 use thiserror::Error;
 
-// TODO: Consider how/if we can rewrite (or omit?) the
-// pubDate/lastPubDate/ttl/skipHours/skipDays channel elements
-
 #[derive(Error, Debug)]
 pub enum RewriteError {
     #[error("Failed to parse feed: {0}")]
-    Parse(#[from] quick_xml::Error),
+    Parse(#[from] Box<ParseError>),
     #[error("Failed to write feed")]
     Write(quick_xml::Error),
 }
 
+impl RewriteError {
+    pub fn parse_error(&self) -> Option<&ParseError> {
+        match self {
+            RewriteError::Parse(err) => Some(err),
+            RewriteError::Write(_) => None,
+        }
+    }
+
+    /// See `ParseError::with_context`.
+    pub fn with_context(self, xml: &[u8]) -> Self {
+        match self {
+            RewriteError::Parse(err) => RewriteError::Parse(Box::new(err.with_context(xml))),
+            err => err,
+        }
+    }
+}
+
+impl Recoverable for RewriteError {
+    fn parse_error(&self) -> Option<&ParseError> {
+        self.parse_error()
+    }
+}
+
+/// Channel level details to use in place of the original feed's. Any that the
+/// feed doesn't have are added.
+#[derive(Debug, Default, Clone)]
+pub struct FeedOverrides {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// The URL of the artwork, for both `itunes:image` and `<image>` (or an
+    /// Atom `<logo>`).
+    pub image: Option<String>,
+    pub author: Option<String>,
+}
+
+/// How to rewrite a feed, beyond the new item timestamps themselves.
+#[derive(Debug, Default, Clone)]
+pub struct RewriteOptions {
+    pub pretty: bool,
+    /// Copy everything that isn't being changed exactly as it was in the
+    /// original, CDATA and whitespace included, so the output differs only
+    /// where it has to. Takes precedence over `pretty`.
+    pub faithful: bool,
+    pub mark_as_private: bool,
+    pub overrides: FeedOverrides,
+    /// When the replay is being served, for working out the channel's `ttl`.
+    pub now: DateTime<Utc>,
+    /// When the next item is due to be replayed, if there is one.
+    pub next_slot: Option<DateTime<Utc>>,
+    /// Put the items in order of their replayed timestamps (newest first)
+    /// rather than leaving them in document order. This means holding them
+    /// all in memory until the end of the channel.
+    pub sort_items: bool,
+    /// Where the replay itself can be found, to replace any links back to
+    /// the original feed. They're dropped entirely if this isn't given.
+    pub self_url: Option<String>,
+    /// Titles to use in place of the items' own, by id (see `TitleTemplate`).
+    pub item_titles: HashMap<String, String>,
+    /// Adds an item to the top of the feed saying how the replay is going.
+    pub status: Option<ReplayStatus>,
+    /// Adds a line to each item's show notes saying when it first came out.
+    pub original_date_note: Option<NotePosition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotePosition {
+    Prepend,
+    Append,
+}
+
+/// Wraps the underlying writer so items can be held back and sorted before
+/// being written, along with everything else that comes between them.
+struct FeedWriter<W: Write> {
+    writer: Writer<W>,
+    sort_items: bool,
+    held: Option<Vec<Held>>,
+}
+
+enum Held {
+    Item(DateTime<Utc>, Vec<Event<'static>>),
+    Other(Event<'static>),
+}
+
+impl<W: Write> FeedWriter<W> {
+    fn write_event(&mut self, ev: Event) -> Result<(), RewriteError> {
+        match &mut self.held {
+            Some(held) => held.push(Held::Other(ev.into_owned())),
+            None => self.writer.write_event(ev).map_err(RewriteError::Write)?,
+        }
+        Ok(())
+    }
+
+    fn write_item(
+        &mut self,
+        replayed_at: DateTime<Utc>,
+        events: Vec<Event<'static>>,
+    ) -> Result<(), RewriteError> {
+        if self.sort_items {
+            let held = self.held.get_or_insert_with(Vec::new);
+            held.push(Held::Item(replayed_at, events));
+            return Ok(());
+        }
+        for ev in events {
+            self.writer.write_event(ev).map_err(RewriteError::Write)?;
+        }
+        Ok(())
+    }
+
+    /// Writes anything held back, with the items rearranged among the places
+    /// items were originally found. Other elements stay where they were.
+    fn release(&mut self) -> Result<(), RewriteError> {
+        let Some(held) = self.held.take() else {
+            return Ok(());
+        };
+        let mut items: Vec<_> = held
+            .iter()
+            .filter_map(|held| match held {
+                Held::Item(replayed_at, events) => Some((replayed_at, events)),
+                Held::Other(_) => None,
+            })
+            .collect();
+        items.sort_by(|(a, _), (b, _)| b.cmp(a));
+        let mut items = items.into_iter();
+        for held in &held {
+            match held {
+                Held::Item(..) => {
+                    let (_, events) = items.next().expect("same number of items");
+                    for ev in events {
+                        self.writer
+                            .write_event(ev.borrow())
+                            .map_err(RewriteError::Write)?;
+                    }
+                }
+                Held::Other(ev) => self
+                    .writer
+                    .write_event(ev.borrow())
+                    .map_err(RewriteError::Write)?,
+            }
+        }
+        Ok(())
+    }
+}
+
 pub fn rewrite_feed(
     xml: &[u8],
     reschedule: &Reschedule<String>,
-    pretty: bool,
-    mark_as_private: bool,
-    custom_title: &Option<String>,
+    options: &RewriteOptions,
 ) -> Result<Vec<u8>, RewriteError> {
-    let reader = quick_xml::Reader::from_reader(xml);
     let mut output = Vec::new();
-    let writer = if pretty {
-        quick_xml::Writer::new_with_indent(&mut output, b' ', 4)
-    } else {
-        quick_xml::Writer::new(&mut output)
-    };
-    rewrite_feed_to_writer(reader, writer, reschedule, mark_as_private, custom_title)?;
+    rewrite_feed_to_writer(xml, &mut output, reschedule, options)
+        .map_err(|err| err.with_context(xml))?;
     Ok(output)
 }
 
-fn write_itunes_block<W: Write>(writer: &mut Writer<W>) -> Result<(), RewriteError> {
+/// Like `rewrite_feed`, but fixes what it can and drops items it can't parse
+/// rather than failing. Returns whatever it had to do along with the feed.
+pub fn rewrite_feed_lenient(
+    xml: &[u8],
+    reschedule: &Reschedule<String>,
+    options: &RewriteOptions,
+) -> Result<(Vec<u8>, Vec<Repair>), RewriteError> {
+    with_recovery(xml, |xml| rewrite_feed(xml, reschedule, options))
+}
+
+fn write_itunes_block<W: Write>(writer: &mut FeedWriter<W>) -> Result<(), RewriteError> {
     for ev in element(BytesStart::new("itunes:block"), "Yes".into()) {
         writer.write_event(ev)?;
     }
     Ok(())
 }
 
-fn rewrite_feed_to_writer<W: Write>(
-    mut reader: quick_xml::Reader<&[u8]>,
-    mut writer: quick_xml::Writer<W>,
+/// Which overrides found something in the original feed to replace.
+#[derive(Default)]
+struct Wrote {
+    description: bool,
+    /// An RSS `<image>` or Atom `<logo>`.
+    image: bool,
+    itunes_image: bool,
+    author: bool,
+}
+
+fn write_missing_overrides<W: Write>(
+    writer: &mut FeedWriter<W>,
+    overrides: &FeedOverrides,
+    wrote: &Wrote,
+    is_atom: bool,
+) -> Result<(), RewriteError> {
+    if let Some(description) = overrides
+        .description
+        .as_deref()
+        .filter(|_| !wrote.description)
+    {
+        let tag = if is_atom { "subtitle" } else { "description" };
+        for ev in element(
+            BytesStart::new(tag),
+            partial_escape(description).into_owned(),
+        ) {
+            writer.write_event(ev)?;
+        }
+    }
+    if let Some(image) = overrides.image.as_deref().filter(|_| !wrote.image) {
+        let image = partial_escape(image).into_owned();
+        if is_atom {
+            for ev in element(BytesStart::new("logo"), image) {
+                writer.write_event(ev)?;
+            }
+        } else {
+            writer.write_event(Event::Start(BytesStart::new("image")))?;
+            for ev in element(BytesStart::new("url"), image) {
+                writer.write_event(ev)?;
+            }
+            writer.write_event(Event::End(BytesEnd::new("image")))?;
+        }
+    }
+    if let Some(image) = overrides.image.as_deref().filter(|_| !wrote.itunes_image) {
+        let mut itunes_image = BytesStart::new("itunes:image");
+        itunes_image.push_attribute(("href", image));
+        writer.write_event(Event::Empty(itunes_image))?;
+    }
+    if let Some(author) = overrides.author.as_deref().filter(|_| !wrote.author) {
+        if is_atom {
+            write_atom_author(writer, author)?;
+        } else {
+            for ev in element(
+                BytesStart::new("itunes:author"),
+                partial_escape(author).into_owned(),
+            ) {
+                writer.write_event(ev)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Dated along with the latest replayed item, so it sits on top without
+/// changing every time the feed is fetched.
+fn write_status_item<W: Write>(
+    writer: &mut FeedWriter<W>,
+    status: &ReplayStatus,
+    is_atom: bool,
+    timestamp: &DateTime<Utc>,
+) -> Result<(), RewriteError> {
+    let (item_tag, id_tag, timestamp_tag, description_tag) = if is_atom {
+        ("entry", "id", "updated", "summary")
+    } else {
+        ("item", "guid", "pubDate", "description")
+    };
+    let mut id = BytesStart::new(id_tag);
+    if !is_atom {
+        id.push_attribute(("isPermaLink", "false"));
+    }
+    writer.write_event(Event::Start(BytesStart::new(item_tag)))?;
+    let title = partial_escape(&status.title()).into_owned();
+    let description = partial_escape(&status.description()).into_owned();
+    let timestamp_str = format_timestamp(timestamp_tag.as_bytes(), timestamp);
+    for ev in [
+        element(BytesStart::new("title"), title),
+        element(BytesStart::new(description_tag), description),
+        element(id, status.id()),
+        element(BytesStart::new(timestamp_tag), timestamp_str),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        writer.write_event(ev)?;
+    }
+    writer.write_event(Event::End(BytesEnd::new(item_tag)))?;
+    Ok(())
+}
+
+fn write_atom_author<W: Write>(
+    writer: &mut FeedWriter<W>,
+    author: &str,
+) -> Result<(), RewriteError> {
+    writer.write_event(Event::Start(BytesStart::new("author")))?;
+    for ev in element(BytesStart::new("name"), partial_escape(author).into_owned()) {
+        writer.write_event(ev)?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("author")))?;
+    Ok(())
+}
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+const NAMESPACES: [(&str, &str); 2] = [
+    ("xmlns:itunes", "http://www.itunes.com/dtds/podcast-1.0.dtd"),
+    ("xmlns:podcast", "https://podcastindex.org/namespace/1.0"),
+];
+
+/// Declares the namespaces used by `write_itunes_block` and
+/// `write_podcast_locked` on the root element, unless the feed already has.
+fn declare_namespaces(root: &mut BytesStart) {
+    for (attr, namespace) in NAMESPACES {
+        if !matches!(root.try_get_attribute(attr), Ok(Some(_))) {
+            root.push_attribute((attr, namespace));
+        }
+    }
+}
+
+fn write_podcast_locked<W: Write>(writer: &mut FeedWriter<W>) -> Result<(), RewriteError> {
+    for ev in element(BytesStart::new("podcast:locked"), "yes".into()) {
+        writer.write_event(ev)?;
+    }
+    Ok(())
+}
+
+/// Rewrites a feed as it's read, writing the output as we go so neither side
+/// needs to be held in memory. As with `FeedSummary::from_reader`, parse
+/// errors only know their position (see `RewriteError::with_context`).
+pub fn rewrite_feed_to_writer<R: BufRead, W: Write>(
+    mut input: R,
+    mut output: W,
     reschedule: &Reschedule<String>,
-    mark_as_private: bool,
-    custom_title: &Option<String>,
+    options: &RewriteOptions,
 ) -> Result<(), RewriteError> {
+    let RewriteOptions {
+        pretty,
+        faithful,
+        mark_as_private,
+        ref overrides,
+        now,
+        next_slot,
+        sort_items,
+        ref self_url,
+        ref item_titles,
+        ref status,
+        original_date_note,
+    } = *options;
+    // The channel as a whole was last updated whenever its newest replayed
+    // item was, rather than whenever the original feed was.
+    let last_updated = reschedule.values().max();
+    if faithful {
+        // The reader quietly skips over any byte order mark, so it has to be
+        // copied across before it gets the chance.
+        let has_bom = input
+            .fill_buf()
+            .map_err(|err| RewriteError::Parse(Box::new(ParseError::at(0, err.into()))))?
+            .starts_with(UTF8_BOM);
+        if has_bom {
+            output
+                .write_all(UTF8_BOM)
+                .map_err(|err| RewriteError::Write(err.into()))?;
+        }
+    }
+    let writer = if pretty && !faithful {
+        quick_xml::Writer::new_with_indent(output, b' ', 4)
+    } else {
+        quick_xml::Writer::new(output)
+    };
+    let mut writer = FeedWriter {
+        writer,
+        sort_items,
+        held: None,
+    };
+    let mut reader = quick_xml::Reader::from_reader(Recorder::new(input));
+    let parse_error = |reader: &quick_xml::Reader<Recorder<R>>, err| {
+        RewriteError::Parse(Box::new(ParseError::at(reader.buffer_position(), err)))
+    };
     let mut buf = Vec::new();
+    let mut at_root = true;
+    let mut wrote = Wrote::default();
+    let mut wrote_status = false;
+    let mut wrote_ttl = false;
+    // Whatever's been started and not yet ended, so overrides only replace
+    // the channel's own title (say) and not its image's.
+    let mut open: Vec<Vec<u8>> = Vec::new();
     loop {
         match reader.read_event_into(&mut buf) {
             Ok(Event::Eof) => break,
-            Ok(Event::Start(start)) => match start.name() {
-                QName(b"item") | QName(b"entry") => {
-                    rewrite_or_skip_item(start, &mut reader, &mut writer, reschedule)?;
-                }
-                QName(b"channel") if mark_as_private => {
-                    writer.write_event(Event::Start(start))?;
-                    write_itunes_block(&mut writer)?;
+            Ok(Event::Start(mut start)) => {
+                if at_root {
+                    at_root = false;
+                    if mark_as_private || overrides.image.is_some() || overrides.author.is_some() {
+                        declare_namespaces(&mut start);
+                    }
                 }
-                QName(b"feed") if mark_as_private => {
-                    let is_atom = start.attributes().filter_map(|a| a.ok()).any(|a| {
-                        a.key == QName(b"xmlns")
-                            && a.value.as_ref() == b"http://www.w3.org/2005/Atom"
-                    });
-                    writer.write_event(Event::Start(start.clone()))?;
-                    if is_atom {
+                let parent = open.last().map(Vec::as_slice);
+                let in_channel = matches!(parent, Some(b"channel" | b"feed"));
+                let in_image = parent == Some(b"image");
+                match start.name() {
+                    QName(b"item") | QName(b"entry") => {
+                        if let Some(status) = status.as_ref().filter(|_| !wrote_status) {
+                            let is_atom = start.name() == QName(b"entry");
+                            let timestamp = last_updated.unwrap_or(&now);
+                            write_status_item(&mut writer, status, is_atom, timestamp)?;
+                            wrote_status = true;
+                        }
+                        let item = rewrite_or_skip_item(
+                            start,
+                            &mut reader,
+                            reschedule,
+                            item_titles,
+                            original_date_note,
+                            faithful,
+                        )
+                        .map_err(|err| parse_error(&reader, err))?;
+                        if let Some((replayed_at, events)) = item {
+                            writer.write_item(replayed_at, events)?;
+                        }
+                    }
+                    QName(b"channel") if mark_as_private => {
+                        open.push(start.name().into_inner().to_vec());
+                        writer.write_event(Event::Start(start))?;
                         write_itunes_block(&mut writer)?;
+                        write_podcast_locked(&mut writer)?;
+                    }
+                    QName(b"feed") if mark_as_private => {
+                        let is_atom = start.attributes().filter_map(|a| a.ok()).any(|a| {
+                            a.key == QName(b"xmlns")
+                                && a.value.as_ref() == b"http://www.w3.org/2005/Atom"
+                        });
+                        open.push(start.name().into_inner().to_vec());
+                        writer.write_event(Event::Start(start.clone()))?;
+                        if is_atom {
+                            write_itunes_block(&mut writer)?;
+                            write_podcast_locked(&mut writer)?;
+                        }
+                    }
+                    QName(b"atom:link") | QName(b"link") if is_self_link(&start) => {
+                        reader
+                            .read_to_end_into(start.name(), &mut Vec::new())
+                            .map_err(|err| parse_error(&reader, err))?;
+                        if let Some(self_url) = self_url {
+                            writer.write_event(Event::Empty(with_href(&start, self_url)))?;
+                        }
+                    }
+                    QName(b"itunes:new-feed-url") => {
+                        // Apps follow this and quietly swap the replay out for the
+                        // original, so there's nothing useful to rewrite it to.
+                        reader
+                            .read_to_end_into(start.name(), &mut Vec::new())
+                            .map_err(|err| parse_error(&reader, err))?;
+                    }
+                    QName(b"podcast:locked") if mark_as_private => {
+                        // We've already written our own, so drop the original
+                        // rather than leave the feed with two conflicting values.
+                        reader
+                            .read_to_end_into(start.name(), &mut Vec::new())
+                            .map_err(|err| parse_error(&reader, err))?;
+                    }
+                    QName(b"pubDate") | QName(b"lastBuildDate") | QName(b"updated") => {
+                        reader
+                            .read_to_end_into(start.name(), &mut Vec::new())
+                            .map_err(|err| parse_error(&reader, err))?;
+                        // Atom requires an updated date, but the rest are optional
+                        // and better left out than leaking the original.
+                        let timestamp = match start.name() {
+                            QName(b"updated") => Some(last_updated.unwrap_or(&now)),
+                            _ => last_updated,
+                        };
+                        if let Some(timestamp) = timestamp {
+                            let timestamp_str =
+                                format_timestamp(start.name().into_inner(), timestamp);
+                            for ev in element(start, timestamp_str) {
+                                writer.write_event(ev)?;
+                            }
+                        }
+                    }
+                    QName(b"ttl") => {
+                        reader
+                            .read_to_end_into(start.name(), &mut Vec::new())
+                            .map_err(|err| parse_error(&reader, err))?;
+                        if let Some(next_slot) = next_slot {
+                            for ev in element(start, ttl_minutes(next_slot, now)) {
+                                writer.write_event(ev)?;
+                            }
+                            wrote_ttl = true;
+                        }
+                    }
+                    QName(b"skipHours") | QName(b"skipDays") => {
+                        // These describe the original schedule, which no longer
+                        // has anything to do with when the replay updates.
+                        reader
+                            .read_to_end_into(start.name(), &mut Vec::new())
+                            .map_err(|err| parse_error(&reader, err))?;
+                    }
+                    QName(b"title") if in_channel => {
+                        let existing_title = read_raw_text(&mut reader, start.name()).ok();
+                        let title = overrides
+                            .title
+                            .as_deref()
+                            .map(|title| partial_escape(title).into_owned())
+                            .or_else(|| existing_title.map(|title| format!("{title} (PodReplay)")))
+                            .unwrap_or_else(|| "Untitled Podreplay Feed".to_string());
+                        for ev in element(start, title) {
+                            writer.write_event(ev)?;
+                        }
+                    }
+                    QName(b"description")
+                    | QName(b"itunes:summary")
+                    | QName(b"itunes:subtitle")
+                    | QName(b"content:encoded")
+                    | QName(b"subtitle")
+                        if in_channel && overrides.description.is_some() =>
+                    {
+                        reader
+                            .read_to_end_into(start.name(), &mut Vec::new())
+                            .map_err(|err| parse_error(&reader, err))?;
+                        let description = overrides.description.as_deref().unwrap_or_default();
+                        for ev in element(start, partial_escape(description).into_owned()) {
+                            writer.write_event(ev)?;
+                        }
+                        wrote.description = true;
+                    }
+                    // An RSS <image> has its <url>, where Atom has a <logo>.
+                    QName(b"url") | QName(b"logo")
+                        if overrides.image.is_some()
+                            && (in_image || start.name() == QName(b"logo")) =>
+                    {
+                        reader
+                            .read_to_end_into(start.name(), &mut Vec::new())
+                            .map_err(|err| parse_error(&reader, err))?;
+                        let image = overrides.image.as_deref().unwrap_or_default();
+                        for ev in element(start, partial_escape(image).into_owned()) {
+                            writer.write_event(ev)?;
+                        }
+                        wrote.image = true;
+                    }
+                    QName(b"itunes:image") if overrides.image.is_some() => {
+                        reader
+                            .read_to_end_into(start.name(), &mut Vec::new())
+                            .map_err(|err| parse_error(&reader, err))?;
+                        let image = overrides.image.as_deref().unwrap_or_default();
+                        writer.write_event(Event::Empty(with_href(&start, image)))?;
+                        wrote.itunes_image = true;
+                    }
+                    QName(b"itunes:author") | QName(b"googleplay:author")
+                        if overrides.author.is_some() =>
+                    {
+                        reader
+                            .read_to_end_into(start.name(), &mut Vec::new())
+                            .map_err(|err| parse_error(&reader, err))?;
+                        let author = overrides.author.as_deref().unwrap_or_default();
+                        for ev in element(start, partial_escape(author).into_owned()) {
+                            writer.write_event(ev)?;
+                        }
+                        wrote.author = true;
                     }
+                    // Only Atom has a channel level <author>, with the name inside.
+                    QName(b"author") if overrides.author.is_some() => {
+                        reader
+                            .read_to_end_into(start.name(), &mut Vec::new())
+                            .map_err(|err| parse_error(&reader, err))?;
+                        let author = overrides.author.as_deref().unwrap_or_default();
+                        write_atom_author(&mut writer, author)?;
+                        wrote.author = true;
+                    }
+                    _ => {
+                        open.push(start.name().into_inner().to_vec());
+                        writer.write_event(Event::Start(start))?;
+                    }
+                }
+            }
+            Ok(Event::Empty(empty))
+                if empty.name() == QName(b"itunes:image") && overrides.image.is_some() =>
+            {
+                let image = overrides.image.as_deref().unwrap_or_default();
+                writer.write_event(Event::Empty(with_href(&empty, image)))?;
+                wrote.itunes_image = true;
+            }
+            Ok(Event::Empty(empty)) if is_self_link(&empty) => {
+                if let Some(self_url) = self_url {
+                    writer.write_event(Event::Empty(with_href(&empty, self_url)))?;
                 }
-                QName(b"title") => {
-                    let existing_title = reader.read_text(start.name()).ok();
-                    let title = custom_title
-                        .clone()
-                        .or_else(|| existing_title.map(|title| format!("{title} (PodReplay)")))
-                        .unwrap_or_else(|| "Untitled Podreplay Feed".to_string());
-                    for ev in element(start, title) {
+            }
+            Ok(Event::Empty(empty)) if empty.name() == QName(b"itunes:new-feed-url") => {}
+            Ok(Event::End(end)) if matches!(end.name(), QName(b"channel") | QName(b"feed")) => {
+                open.pop();
+                writer.release()?;
+                let is_atom = end.name() == QName(b"feed");
+                if let Some(status) = status.as_ref().filter(|_| !wrote_status) {
+                    // There weren't any items to put it in front of.
+                    let timestamp = last_updated.unwrap_or(&now);
+                    write_status_item(&mut writer, status, is_atom, timestamp)?;
+                    wrote_status = true;
+                }
+                write_missing_overrides(&mut writer, overrides, &wrote, is_atom)?;
+                // Atom has no equivalent.
+                if let Some(next_slot) = next_slot.filter(|_| !wrote_ttl && !is_atom) {
+                    for ev in element(BytesStart::new("ttl"), ttl_minutes(next_slot, now)) {
                         writer.write_event(ev)?;
                     }
                 }
-                _ => {
-                    writer.write_event(Event::Start(start))?;
-                }
-            },
+                writer.write_event(Event::End(end_tag(&reader, end, faithful)))?;
+            }
+            Ok(Event::End(end)) => {
+                open.pop();
+                writer.write_event(Event::End(end_tag(&reader, end, faithful)))?;
+            }
             Ok(ev) => {
-                writer.write_event(ev).map_err(RewriteError::Write)?;
+                writer.write_event(ev)?;
             }
             Err(e) => {
                 tracing::error!("Error at position {}: {:?}", reader.buffer_position(), e);
-                return Err(RewriteError::Parse(e));
+                return Err(parse_error(&reader, e));
             }
         }
         buf.clear();
     }
+    writer.release()?;
     Ok(())
 }
-//hello
-fn rewrite_or_skip_item<B: BufRead, W: Write>(
+
+/// How many minutes until the next slot, rounded up so clients don't check
+/// back just before the next item shows up.
+fn ttl_minutes(next_slot: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let seconds = (next_slot - now).num_seconds();
+    ((seconds + 59) / 60).max(1).to_string()
+}
+
+type ReplayedItem = (DateTime<Utc>, Vec<Event<'static>>);
+
+/// Reads the rest of an item, returning when it was replayed and the events to
+/// write in its place, or `None` if it should be left out of the replay
+/// entirely.
+fn rewrite_or_skip_item<B: BufRead>(
     start: BytesStart,
-    reader: &mut Reader<B>,
-    writer: &mut Writer<W>,
+    reader: &mut Reader<Recorder<B>>,
     reschedule: &Reschedule<String>,
-) -> Result<(), quick_xml::Error> {
+    item_titles: &HashMap<String, String>,
+    original_date_note: Option<NotePosition>,
+    faithful: bool,
+) -> Result<Option<ReplayedItem>, quick_xml::Error> {
     let item_tag = start.name();
     let mut buf = Vec::new();
     let mut events = Vec::new();
     let mut skipped_timestamp: Option<(usize, BytesStart)> = None;
     let mut target_timestamp: Option<DateTime<Utc>> = None;
-    let mut had_id = false;
+    let mut replayed_at = None;
+    let mut title = None;
+    let mut original_timestamp = None;
     let mut had_timestamp = false;
     let mut had_enclosure = false;
     loop {
@@ -120,25 +663,45 @@ fn rewrite_or_skip_item<B: BufRead, W: Write>(
                 // and timestamp. I can imagine some random feeds missing one of
                 // these things, but any sane podcast feed should have them. If
                 // an item doesn't, we just skip it.
-                if had_id && had_timestamp && had_enclosure {
-                    writer.write_event(Event::Start(start))?;
-                    for ev in events {
-                        writer.write_event(ev)?;
+                match replayed_at {
+                    Some(replayed_at) if had_timestamp && had_enclosure => {
+                        if let Some(title) = title {
+                            replace_item_titles(&mut events, title);
+                        }
+                        if let (Some(position), Some(original_timestamp)) =
+                            (original_date_note, original_timestamp)
+                        {
+                            add_original_date_note(&mut events, position, &original_timestamp);
+                        }
+                        events.insert(0, Event::Start(start.into_owned()));
+                        events.push(Event::End(end_tag(reader, end, faithful)));
+                        return Ok(Some((replayed_at, events)));
                     }
-                    writer.write_event(Event::End(end))?;
+                    _ => return Ok(None),
                 }
-                return Ok(());
             }
             Ok(Event::Start(start)) => {
                 let element_tag = start.name();
                 let mut start_buf = Vec::new();
                 match element_tag {
                     QName(b"guid") | QName(b"id") => {
-                        had_id = true;
-                        let guid = read_contents(reader, &start)?;
+                        let mut contents = Vec::new();
+                        let guid = read_contents_into(reader, &start, &mut contents)?;
 
                         if let Some(rescheduled_timestamp) = reschedule.get(&guid) {
-                            events.extend(element(start.into_owned(), guid));
+                            replayed_at = Some(*rescheduled_timestamp);
+                            title = item_titles.get(&guid).map(String::as_str);
+                            if faithful {
+                                if let Some(Event::End(end)) = contents.pop() {
+                                    contents.push(Event::End(end_tag(reader, end, faithful)));
+                                }
+                                events.push(Event::Start(start.into_owned()));
+                                events.extend(contents);
+                            } else {
+                                // read_contents unescapes, so it needs escaping again.
+                                let escaped = partial_escape(&guid).into_owned();
+                                events.extend(element(start.into_owned(), escaped));
+                            }
 
                             if let Some((ts_index, ts_start)) = skipped_timestamp.take() {
                                 // The original timestamp element was placed before
@@ -154,12 +717,19 @@ fn rewrite_or_skip_item<B: BufRead, W: Write>(
                             }
                         } else {
                             reader.read_to_end_into(item_tag, &mut start_buf)?;
-                            return Ok(());
+                            return Ok(None);
                         }
                     }
                     QName(b"pubDate") | QName(b"updated") => {
                         had_timestamp = true;
-                        reader.read_to_end_into(element_tag, &mut start_buf)?;
+                        // It's replaced either way, so one we can't read just
+                        // goes without a note of the original date.
+                        let original = match read_raw_text(reader, element_tag) {
+                            Ok(original) => Some(original),
+                            Err(quick_xml::Error::NonDecodable(_)) => None,
+                            Err(err) => return Err(err),
+                        };
+                        original_timestamp = original.and_then(|ts| parse_timestamp(&ts));
                         if let Some(target_timestamp) = target_timestamp.take() {
                             let timestamp_str =
                                 format_timestamp(element_tag.into_inner(), &target_timestamp);
@@ -192,6 +762,9 @@ fn rewrite_or_skip_item<B: BufRead, W: Write>(
 
                 events.push(Event::Empty(empty.into_owned()));
             }
+            Ok(Event::End(end)) => {
+                events.push(Event::End(end_tag(reader, end, faithful)));
+            }
             Ok(ev) => {
                 events.push(ev.into_owned());
             }
@@ -204,9 +777,238 @@ fn rewrite_or_skip_item<B: BufRead, W: Write>(
     }
 }
 
+/// How much of what it's read the `Recorder` keeps hold of, which only needs
+/// to cover the closing tag read last.
+const RECORDED: usize = 256;
+
+/// Keeps the last few bytes the XML reader took from the input. quick-xml
+/// trims any whitespace from the end of a closing tag (`</title >`), so this
+/// is where faithful rewrites find out what was actually there.
+struct Recorder<R> {
+    inner: R,
+    recent: Vec<u8>,
+}
+
+impl<R> Recorder<R> {
+    fn new(inner: R) -> Self {
+        Recorder {
+            inner,
+            recent: Vec::with_capacity(RECORDED),
+        }
+    }
+}
+
+fn record(recent: &mut Vec<u8>, bytes: &[u8]) {
+    let bytes = &bytes[bytes.len().saturating_sub(RECORDED)..];
+    let excess = (recent.len() + bytes.len()).saturating_sub(RECORDED);
+    recent.drain(..excess);
+    recent.extend_from_slice(bytes);
+}
+
+impl<R: BufRead> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        record(&mut self.recent, &buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for Recorder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let Recorder { inner, recent } = self;
+        // Already filled, so this doesn't read anything more.
+        if let Ok(buf) = inner.fill_buf() {
+            record(recent, &buf[..amt.min(buf.len())]);
+        }
+        inner.consume(amt);
+    }
+}
+
+/// The closing tag just read, spaced out the way it was in the original if
+/// we're being faithful to it.
+fn end_tag<R>(reader: &Reader<Recorder<R>>, end: BytesEnd, faithful: bool) -> BytesEnd<'static> {
+    let written = reader.get_ref().recent.strip_suffix(b">");
+    let name = end.name();
+    let space = written.and_then(|written| {
+        let trimmed = written.len()
+            - written
+                .iter()
+                .rev()
+                .take_while(|b| b.is_ascii_whitespace())
+                .count();
+        let (tag, space) = written.split_at(trimmed);
+        let is_this_tag = tag
+            .strip_suffix(name.as_ref())
+            .map_or(false, |rest| rest.ends_with(b"/"));
+        (is_this_tag && !space.is_empty()).then_some(space)
+    });
+    match space.filter(|_| faithful) {
+        Some(space) => BytesEnd::new(format!(
+            "{}{}",
+            String::from_utf8_lossy(name.as_ref()),
+            String::from_utf8_lossy(space)
+        )),
+        None => end.into_owned(),
+    }
+}
+
+fn is_self_link(start: &BytesStart) -> bool {
+    matches!(start.name(), QName(b"atom:link") | QName(b"link"))
+        && start
+            .try_get_attribute("rel")
+            .ok()
+            .flatten()
+            .map_or(false, |rel| rel.value.as_ref() == b"self")
+}
+
+/// A copy of the link pointing somewhere else, keeping any other attributes.
+fn with_href(start: &BytesStart, href: &str) -> BytesStart<'static> {
+    let mut link = BytesStart::new(String::from_utf8_lossy(start.name().as_ref()).into_owned());
+    let mut had_href = false;
+    for attr in start.attributes().filter_map(|a| a.ok()) {
+        if attr.key == QName(b"href") {
+            had_href = true;
+            link.push_attribute(("href", href));
+        } else {
+            link.push_attribute(attr);
+        }
+    }
+    if !had_href {
+        link.push_attribute(("href", href));
+    }
+    link
+}
+
+/// Adds "Originally published …" to the start or end of an item's show notes,
+/// in whatever form they're in. RSS bodies are assumed to be HTML, while Atom
+/// ones say what they are (plain text unless told otherwise).
+fn add_original_date_note(
+    events: &mut Vec<Event<'static>>,
+    position: NotePosition,
+    original_timestamp: &DateTime<Utc>,
+) {
+    let note = format!(
+        "Originally published {}",
+        original_timestamp.format("%B %-d, %Y")
+    );
+    let is_body = |name: QName| {
+        matches!(
+            name,
+            QName(b"description")
+                | QName(b"content:encoded")
+                | QName(b"summary")
+                | QName(b"content")
+        )
+    };
+    for range in top_level_elements(events, is_body).into_iter().rev() {
+        let (start, end) = range.into_inner();
+        let Event::Start(body) = &events[start] else {
+            unreachable!("ranges begin with a start event");
+        };
+        let content_type = body
+            .try_get_attribute("type")
+            .ok()
+            .flatten()
+            .map(|attr| attr.value.into_owned());
+        let is_atom = matches!(body.name(), QName(b"summary") | QName(b"content"));
+        let (start, end, note) = match content_type.as_deref() {
+            // The content is wrapped in a single <div>, which the note needs
+            // to go inside of.
+            Some(b"xhtml") => {
+                let div = top_level_elements(&events[start + 1..end], |_| true)
+                    .into_iter()
+                    .next();
+                let Some(div) = div else { continue };
+                let p = element(BytesStart::new("p"), partial_escape(&note).into_owned());
+                let (div_start, div_end) = div.into_inner();
+                (start + 1 + div_start, start + 1 + div_end, Vec::from(p))
+            }
+            Some(b"text") | None if is_atom => {
+                let note = match position {
+                    NotePosition::Prepend => format!("{note}\n\n"),
+                    NotePosition::Append => format!("\n\n{note}"),
+                };
+                (start, end, vec![text_like(&events[start..end], note)])
+            }
+            _ => {
+                let note = format!("<p>{note}</p>");
+                (start, end, vec![text_like(&events[start..end], note)])
+            }
+        };
+        let index = match position {
+            NotePosition::Prepend => start + 1,
+            NotePosition::Append => end,
+        };
+        events.splice(index..index, note);
+    }
+}
+
+/// Matches CDATA if that's what the body already uses, since some apps
+/// only look at that.
+fn text_like(body: &[Event], text: String) -> Event<'static> {
+    if body.iter().any(|ev| matches!(ev, Event::CData(_))) {
+        Event::CData(BytesCData::new(text))
+    } else {
+        Event::Text(BytesText::new(&text).into_owned())
+    }
+}
+
+/// The ranges of the direct children (from their start to end events) that
+/// match the given names.
+fn top_level_elements(
+    events: &[Event],
+    matches: impl Fn(QName) -> bool,
+) -> Vec<RangeInclusive<usize>> {
+    let mut ranges = Vec::new();
+    let mut depth = 0;
+    let mut start_index = None;
+    for (index, ev) in events.iter().enumerate() {
+        match ev {
+            Event::Start(start) => {
+                if depth == 0 && matches(start.name()) {
+                    start_index = Some(index);
+                }
+                depth += 1;
+            }
+            Event::End(_) => {
+                depth -= 1;
+                if depth == 0 {
+                    if let Some(start_index) = start_index.take() {
+                        ranges.push(start_index..=index);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+/// Swaps out the content of an item's own titles, or adds one if it had none.
+fn replace_item_titles(events: &mut Vec<Event<'static>>, title: &str) {
+    let is_title = |name: QName| matches!(name, QName(b"title") | QName(b"itunes:title"));
+    let ranges = top_level_elements(events, is_title);
+    let escaped = partial_escape(title);
+    if ranges.is_empty() {
+        events.extend(element(BytesStart::new("title"), escaped.into_owned()));
+        return;
+    }
+    for range in ranges.into_iter().rev() {
+        let Event::Start(start) = &events[*range.start()] else {
+            unreachable!("ranges begin with a start event");
+        };
+        let replacement = element(start.clone(), escaped.to_string());
+        events.splice(range, replacement);
+    }
+}
+
 fn format_timestamp(element_tag: &[u8], target_timestamp: &DateTime<Utc>) -> String {
     match element_tag {
-        b"pubDate" => target_timestamp.to_rfc2822(),
+        b"pubDate" | b"lastBuildDate" => target_timestamp.to_rfc2822(),
         _ => target_timestamp.to_rfc3339_opts(SecondsFormat::Secs, true),
     }
 }
@@ -224,17 +1026,35 @@ fn element(start: BytesStart, content: String) -> [Event; 3] {
 mod tests {
     use std::collections::HashMap;
 
-    use crate::{reschedule::Reschedule, test_helpers::parse_dt};
+    use crate::{
+        reschedule::Reschedule, test_helpers::parse_dt, FeedSummary, RepairKind, ReplayStatus,
+    };
 
-    use super::rewrite_feed;
+    use super::{
+        rewrite_feed, rewrite_feed_lenient, rewrite_feed_to_writer, FeedOverrides, NotePosition,
+        RewriteOptions,
+    };
     use pretty_assertions::assert_eq;
 
+    fn options(pretty: bool, mark_as_private: bool, title: Option<String>) -> RewriteOptions {
+        RewriteOptions {
+            pretty,
+            mark_as_private,
+            overrides: FeedOverrides {
+                title,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
     fn parse_feed_to_str(
         xml: &str,
         reschedule: &Reschedule<String>,
         title: Option<String>,
     ) -> String {
-        let output = rewrite_feed(xml.as_bytes(), reschedule, true, false, &title).unwrap();
+        let output =
+            rewrite_feed(xml.as_bytes(), reschedule, &options(true, false, title)).unwrap();
         String::from_utf8(output).unwrap()
     }
 
@@ -247,9 +1067,10 @@ mod tests {
         )]);
         let output = parse_feed_to_str(xml, &reschedule, Some("Hello World".to_string()));
         let expected = xml
+            // Both the entry and the feed as a whole.
             .replace(
-                "        <updated>2003-12-13T18:30:02Z</updated>",
-                "        <updated>2021-12-13T16:00:00Z</updated>",
+                "<updated>2003-12-13T18:30:02Z</updated>",
+                "<updated>2021-12-13T16:00:00Z</updated>",
             )
             .replace("<title>Example Feed</title>", "<title>Hello World</title>");
         assert_eq!(output, expected);
@@ -280,6 +1101,11 @@ mod tests {
                 "<pubDate>Sun, 29 Sep 2002 19:59:01 GMT</pubDate>",
                 "<pubDate>Mon, 20 Dec 2021 16:00:00 +0000</pubDate>",
             )
+            .replace(
+                "<lastBuildDate>Mon, 30 Sep 2002 11:00:00 GMT</lastBuildDate>",
+                "<lastBuildDate>Mon, 20 Dec 2021 16:00:00 +0000</lastBuildDate>",
+            )
+            .replace("<ttl>40</ttl>", "")
             .replace(
                 "<title>Scripting News</title>",
                 "<title>Scripting News (PodReplay)</title>",
@@ -287,6 +1113,380 @@ mod tests {
         assert_eq!(output, expected);
     }
 
+    #[test]
+    fn escapes_custom_title_and_guids() {
+        let xml = r#"<rss><channel>
+            <title>Escaping</title>
+            <item><guid>a&amp;b</guid><pubDate>Sun, 29 Sep 2002 19:59:01 GMT</pubDate><enclosure url="https://example.com/a.mp3" type="audio/mpeg" /></item>
+        </channel></rss>"#;
+        let reschedule = HashMap::from([("a&b".to_string(), parse_dt("2021-12-13T16:00:00"))]);
+        let output = parse_feed_to_str(xml, &reschedule, Some("Tom & Jerry".to_string()));
+        assert!(output.contains("<title>Tom &amp; Jerry</title>"));
+        assert!(output.contains("<guid>a&amp;b</guid>"));
+    }
+
+    #[test]
+    fn replaces_undecodable_item_dates() {
+        let xml = b"<rss><channel>
+            <item><guid>a</guid><pubDate>Sun, 29 Sep 2002 \xff</pubDate><enclosure url=\"https://example.com/a.mp3\" type=\"audio/mpeg\" /></item>
+        </channel></rss>";
+        let reschedule = HashMap::from([("a".to_string(), parse_dt("2021-12-13T16:00:00"))]);
+        let options = RewriteOptions {
+            original_date_note: Some(NotePosition::Append),
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml, &reschedule, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("<pubDate>Mon, 13 Dec 2021 16:00:00 +0000</pubDate>"));
+        assert!(!output.contains("Originally published"));
+    }
+
+    #[test]
+    fn adds_missing_ttl() {
+        let xml = r#"<rss><channel>
+            <title>Timing</title>
+            <item><guid>a</guid><pubDate>Sun, 29 Sep 2002 19:59:01 GMT</pubDate></item>
+        </channel></rss>"#;
+        let reschedule = HashMap::from([("a".to_string(), parse_dt("2021-12-13T16:00:00"))]);
+        let options = RewriteOptions {
+            now: parse_dt("2021-12-14T12:00:00"),
+            next_slot: Some(parse_dt("2021-12-14T14:30:30")),
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &reschedule, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output
+            .trim_end()
+            .ends_with("<ttl>151</ttl></channel></rss>"));
+
+        // Nothing left to wait for.
+        let options = RewriteOptions {
+            next_slot: None,
+            ..options
+        };
+        let output = rewrite_feed(xml.as_bytes(), &reschedule, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("<ttl>"));
+    }
+
+    #[test]
+    fn channel_timing() {
+        let xml = r#"<rss><channel>
+            <title>Timing</title>
+            <lastBuildDate>Mon, 30 Sep 2002 11:00:00 GMT</lastBuildDate>
+            <pubDate>Mon, 30 Sep 2002 11:00:00 GMT</pubDate>
+            <ttl>40</ttl>
+            <skipHours><hour>0</hour><hour>1</hour></skipHours>
+            <skipDays><day>Saturday</day></skipDays>
+            <item><guid>a</guid><pubDate>Sun, 29 Sep 2002 19:59:01 GMT</pubDate></item>
+        </channel></rss>"#;
+        let reschedule = HashMap::from([("a".to_string(), parse_dt("2021-12-13T16:00:00"))]);
+        let options = RewriteOptions {
+            now: parse_dt("2021-12-14T12:00:00"),
+            next_slot: Some(parse_dt("2021-12-14T14:30:30")),
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &reschedule, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("<lastBuildDate>Mon, 13 Dec 2021 16:00:00 +0000</lastBuildDate>"));
+        assert!(output.contains("<pubDate>Mon, 13 Dec 2021 16:00:00 +0000</pubDate>"));
+        assert!(output.contains("<ttl>151</ttl>"));
+        assert_eq!(output.matches("<ttl>").count(), 1);
+        assert!(!output.contains("skipHours"));
+        assert!(!output.contains("skipDays"));
+
+        // Nothing has been replayed yet, so there's nothing to date the channel by.
+        let output = rewrite_feed(xml.as_bytes(), &HashMap::new(), &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("lastBuildDate"));
+        assert!(!output.contains("pubDate"));
+    }
+
+    #[test]
+    fn sort_items() {
+        let xml = include_str!("../tests/data/sample_rss_2.0.xml");
+        let first = "http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM";
+        let second = "http://scriptingnews.userland.com/backissues/2002/09/29#When:12:59:01PM";
+        let reschedule = HashMap::from([
+            (first.to_string(), parse_dt("2021-12-13T16:00:00")),
+            (second.to_string(), parse_dt("2021-12-20T16:00:00")),
+        ]);
+        let unsorted = parse_feed_to_str(xml, &reschedule, None);
+        assert!(unsorted.find(first) < unsorted.find(second));
+
+        let options = RewriteOptions {
+            pretty: true,
+            sort_items: true,
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &reschedule, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.find(second) < output.find(first));
+        // The items swap places, but everything around them stays put.
+        let swapped = {
+            let start = unsorted.find("<item>").unwrap();
+            let end = unsorted.rfind("</item>").unwrap() + "</item>".len();
+            let items = &unsorted[start..end];
+            let split = items.find("</item>").unwrap() + "</item>".len();
+            let (a, rest) = items.split_at(split);
+            let gap_end = rest.find("<item>").unwrap();
+            let (gap, b) = rest.split_at(gap_end);
+            format!("{}{b}{gap}{a}{}", &unsorted[..start], &unsorted[end..])
+        };
+        assert_eq!(output, swapped);
+    }
+
+    #[test]
+    fn self_links() {
+        let xml = r#"<rss xmlns:atom="http://www.w3.org/2005/Atom"><channel>
+            <atom:link href="https://example.com/feed" rel="self" type="application/rss+xml" />
+            <atom:link href="https://example.com/hub" rel="hub" />
+            <itunes:new-feed-url>https://example.com/new-feed</itunes:new-feed-url>
+            <link>https://example.com/</link>
+        </channel></rss>"#;
+        let options = RewriteOptions {
+            self_url: Some("https://podreplay.com/replay?rule=1w&uri=x".to_string()),
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &HashMap::new(), &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(
+            r#"<atom:link href="https://podreplay.com/replay?rule=1w&amp;uri=x" rel="self" type="application/rss+xml"/>"#
+        ));
+        assert!(output.contains(r#"<atom:link href="https://example.com/hub" rel="hub" />"#));
+        assert!(output.contains("<link>https://example.com/</link>"));
+        assert!(!output.contains("new-feed"));
+    }
+
+    #[test]
+    fn overrides() {
+        let xml = include_str!("../tests/data/megaphone.xml");
+        let options = RewriteOptions {
+            overrides: FeedOverrides {
+                title: Some("Slow Burn".to_string()),
+                description: Some("Season 6 with the <b>book club</b>".to_string()),
+                image: Some("https://example.com/club.png".to_string()),
+                author: Some("Book Club".to_string()),
+            },
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &HashMap::new(), &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let channel = &output[..output.find("</channel>").unwrap()];
+        for expected in [
+            "<title>Slow Burn</title>",
+            "<description>Season 6 with the &lt;b&gt;book club&lt;/b&gt;</description>",
+            "<itunes:summary>Season 6 with the &lt;b&gt;book club&lt;/b&gt;</itunes:summary>",
+            "<url>https://example.com/club.png</url>",
+            r#"<itunes:image href="https://example.com/club.png"/>"#,
+            "<itunes:author>Book Club</itunes:author>",
+        ] {
+            assert!(channel.contains(expected), "missing {expected}");
+        }
+        assert!(!channel.contains("Rodney King"));
+        assert!(!channel.contains("megaphone.imgix.net"));
+        assert!(!channel.contains("Slate Podcasts"));
+    }
+
+    #[test]
+    fn missing_overrides() {
+        let xml = include_str!("../tests/data/sample_atom.xml");
+        let options = RewriteOptions {
+            overrides: FeedOverrides {
+                description: Some("Replayed".to_string()),
+                image: Some("https://example.com/club.png".to_string()),
+                author: Some("Book Club".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &HashMap::new(), &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(r#"xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd""#));
+        assert!(output.contains("<author><name>Book Club</name></author>"));
+        assert!(!output.contains("John Doe"));
+        assert!(output.trim_end().ends_with(
+            r#"<subtitle>Replayed</subtitle><logo>https://example.com/club.png</logo><itunes:image href="https://example.com/club.png"/></feed>"#
+        ));
+    }
+
+    fn image_options() -> RewriteOptions {
+        RewriteOptions {
+            overrides: FeedOverrides {
+                image: Some("https://example.com/club.png".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn adds_missing_itunes_image() {
+        let xml = r#"<rss><channel>
+            <url>https://example.com/elsewhere</url>
+            <image><url>https://example.com/old.png</url><title>Old</title></image>
+        </channel></rss>"#;
+        let output = rewrite_feed(xml.as_bytes(), &HashMap::new(), &image_options()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        // Only the <url> inside <image> is the artwork.
+        assert!(output.contains("<url>https://example.com/elsewhere</url>"));
+        assert!(output.contains("<image><url>https://example.com/club.png</url><title>"));
+        assert!(!output.contains("old.png"));
+        assert!(output
+            .trim_end()
+            .ends_with(r#"<itunes:image href="https://example.com/club.png"/></channel></rss>"#));
+        assert_eq!(output.matches("<image>").count(), 1);
+    }
+
+    #[test]
+    fn only_overrides_the_channel_itself() {
+        let xml = r#"<rss><channel>
+            <title>Old</title>
+            <description>Old notes</description>
+            <image><url>https://example.com/old.png</url><title>Artwork</title><description>Artwork notes</description></image>
+            <textInput><title>Search</title><description>Search notes</description></textInput>
+        </channel></rss>"#;
+        let options = RewriteOptions {
+            overrides: FeedOverrides {
+                title: Some("New".to_string()),
+                description: Some("New notes".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &HashMap::new(), &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("<title>New</title>"));
+        assert!(output.contains("<description>New notes</description>"));
+        assert!(output.contains("<title>Artwork</title><description>Artwork notes</description>"));
+        assert!(output.contains("<title>Search</title><description>Search notes</description>"));
+        assert!(!output.contains("Old"));
+    }
+
+    #[test]
+    fn adds_missing_image() {
+        let xml = r#"<rss><channel>
+            <itunes:image href="https://example.com/old.png" />
+        </channel></rss>"#;
+        let output = rewrite_feed(xml.as_bytes(), &HashMap::new(), &image_options()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(r#"<itunes:image href="https://example.com/club.png"/>"#));
+        assert!(!output.contains("old.png"));
+        assert!(output
+            .trim_end()
+            .ends_with("<image><url>https://example.com/club.png</url></image></channel></rss>"));
+        assert_eq!(output.matches("itunes:image").count(), 1);
+    }
+
+    #[test]
+    fn item_titles() {
+        let xml = include_str!("../tests/data/sample_rss_2.0.xml");
+        let first = "http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM";
+        let second = "http://scriptingnews.userland.com/backissues/2002/09/29#When:12:59:01PM";
+        let reschedule = HashMap::from([
+            (first.to_string(), parse_dt("2021-12-13T16:00:00")),
+            (second.to_string(), parse_dt("2021-12-20T16:00:00")),
+        ]);
+        let options = RewriteOptions {
+            pretty: true,
+            item_titles: HashMap::from([
+                (first.to_string(), "2/2 — Untitled".to_string()),
+                (second.to_string(), "1/2 — Joshua & namespaces".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &reschedule, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        // The first item didn't have a title, so it gets one.
+        let first_item = &output[output.find(first).unwrap()..output.find(second).unwrap()];
+        assert!(first_item.contains("<title>2/2 — Untitled</title>"));
+        assert!(output.contains("<title>1/2 — Joshua &amp; namespaces</title>"));
+        assert!(!output.contains("<title>Joshua Allen"));
+    }
+
+    #[test]
+    fn status_item() {
+        let xml = include_str!("../tests/data/sample_rss_2.0.xml");
+        let first = "http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM";
+        let reschedule = HashMap::from([(first.to_string(), parse_dt("2021-12-13T16:00:00"))]);
+        let next_slot = Some(parse_dt("2021-12-20T16:00:00"));
+        let options = RewriteOptions {
+            pretty: true,
+            status: Some(ReplayStatus::new(&reschedule, next_slot, 2)),
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &reschedule, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let status = "<item>
+            <title>Next episode on Monday, December 20, 2021</title>
+            <description>1 of 2 episodes replayed. The next one will be out on Monday, December 20, 2021.</description>
+            <guid isPermaLink=\"false\">podreplay-status-1-1640016000</guid>
+            <pubDate>Mon, 13 Dec 2021 16:00:00 +0000</pubDate>
+        </item>";
+        assert!(output.contains(status), "{output}");
+        assert!(output.find(status) < output.find(first));
+
+        let xml = include_str!("../tests/data/sample_atom.xml");
+        let options = RewriteOptions {
+            status: Some(ReplayStatus::new(&HashMap::<String, _>::new(), None, 0)),
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &HashMap::new(), &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(
+            "<entry><title>Replay complete, now following live</title><summary>All 0 episodes have been replayed. New episodes will show up as they're published.</summary><id>podreplay-status-0-complete</id><updated>1970-01-01T00:00:00Z</updated></entry>"
+        ), "{output}");
+    }
+
+    #[test]
+    fn original_date_note() {
+        let xml = include_str!("../tests/data/megaphone.xml");
+        let reschedule = HashMap::from([(
+            "614f5f12-4f9c-11eb-a6af-cb9557e04485".to_string(),
+            parse_dt("2022-01-17T16:00:00"),
+        )]);
+        let options = RewriteOptions {
+            original_date_note: Some(NotePosition::Append),
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &reschedule, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let item = &output[output.find("<item>").unwrap()..];
+        // Escaped HTML stays escaped, and CDATA gets some more CDATA.
+        assert!(item.contains(
+            "megaphone.fm/adchoices&lt;p&gt;Originally published December 29, 2021&lt;/p&gt;</description>"
+        ));
+        assert!(item.contains(
+            "<![CDATA[<p>Originally published December 29, 2021</p>]]></content:encoded>"
+        ));
+        // Only the replayed item's notes.
+        assert_eq!(output.matches("Originally published").count(), 2);
+    }
+
+    #[test]
+    fn original_date_note_atom() {
+        let xml = include_str!("../tests/data/sample_atom.xml").replace(
+            "<summary>Some text.</summary>",
+            r#"<summary>Some text.</summary>
+        <content type="xhtml"><div xmlns="http://www.w3.org/1999/xhtml"><p>Some more.</p></div></content>"#,
+        );
+        let reschedule = HashMap::from([(
+            "urn:uuid:1225c695-cfb8-4ebb-aaaa-80da344efa6a".to_string(),
+            parse_dt("2021-12-13T16:00:00"),
+        )]);
+        let options = RewriteOptions {
+            original_date_note: Some(NotePosition::Prepend),
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &reschedule, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output
+            .contains("<summary>Originally published December 13, 2003\n\nSome text.</summary>"));
+        assert!(output.contains(
+            r#"<div xmlns="http://www.w3.org/1999/xhtml"><p>Originally published December 13, 2003</p><p>Some more.</p></div>"#
+        ));
+    }
+
     #[test]
     fn megaphone() {
         let xml = include_str!("../tests/data/megaphone.xml");
@@ -306,6 +1506,10 @@ mod tests {
         ]);
         let output = parse_feed_to_str(xml, &reschedule, None);
         let expected = xml
+            .replace(
+                "<atom:link href=\"https://feeds.megaphone.fm/watergate\" rel=\"self\" type=\"application/rss+xml\" />",
+                "",
+            )
             .replace(
                 "<guid isPermaLink=\"false\">\n                <![CDATA[",
                 "<guid isPermaLink=\"false\">",
@@ -331,13 +1535,214 @@ mod tests {
                 "<pubDate>Wed, 29 Dec 2021 08:00:00 -0000</pubDate>",
                 "<pubDate>Mon, 17 Jan 2022 16:00:00 +0000</pubDate>",
             )
+            // Only the channel's, not its image's.
+            .replacen(
+                "<title>Slow Burn</title>",
+                "<title>Slow Burn (PodReplay)</title>",
+                1,
+            );
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn faithful() {
+        let xml = include_str!("../tests/data/megaphone.xml");
+        let reschedule = HashMap::from([
+            (
+                "612990fc-4f9c-11eb-a6af-e7830eb4fc55".to_string(),
+                parse_dt("2022-01-15T16:00:00"),
+            ),
+            (
+                "613b2312-4f9c-11eb-a6af-b700e1b799da".to_string(),
+                parse_dt("2022-01-16T16:00:00"),
+            ),
+            (
+                "614f5f12-4f9c-11eb-a6af-cb9557e04485".to_string(),
+                parse_dt("2022-01-17T16:00:00"),
+            ),
+        ]);
+        let options = RewriteOptions {
+            // Ignored in favour of keeping the original formatting.
+            pretty: true,
+            faithful: true,
+            ..Default::default()
+        };
+        let input = format!("\u{FEFF}{xml}");
+        let output = rewrite_feed(input.as_bytes(), &reschedule, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        // Unlike the megaphone test, the CDATA guids and empty elements are
+        // left alone, along with the byte order mark.
+        let expected = input
+            .replace(
+                "<atom:link href=\"https://feeds.megaphone.fm/watergate\" rel=\"self\" type=\"application/rss+xml\" />",
+                "",
+            )
+            .replace(
+                "<pubDate>Wed, 15 Dec 2021 08:00:00 -0000</pubDate>",
+                "<pubDate>Sat, 15 Jan 2022 16:00:00 +0000</pubDate>",
+            )
             .replace(
+                "<pubDate>Wed, 22 Dec 2021 08:00:00 -0000</pubDate>",
+                "<pubDate>Sun, 16 Jan 2022 16:00:00 +0000</pubDate>",
+            )
+            .replace(
+                "<pubDate>Wed, 29 Dec 2021 08:00:00 -0000</pubDate>",
+                "<pubDate>Mon, 17 Jan 2022 16:00:00 +0000</pubDate>",
+            )
+            // Only the channel's, not its image's.
+            .replacen(
                 "<title>Slow Burn</title>",
                 "<title>Slow Burn (PodReplay)</title>",
+                1,
+            );
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn faithful_byte_for_byte() {
+        let xml = "<?xml version='1.0' encoding='UTF-8'?>\r\n<!-- generated -->\
+            <rss version='2.0'  xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\"><channel\r\n>\
+            <title >Caf&#xe9; &amp; Friends</title ><link>https://example.com/?a=1&amp;b=2</link>\
+            <itunes:image\thref='https://example.com/art.png'/><?note keep me?>\
+            <item><title>One &#8211; &quot;first&quot;</title><guid isPermaLink='false'>a</guid\t>\
+            <pubDate>Sun, 29 Sep 2002 19:59:01 GMT</pubDate >\
+            <enclosure type=\"audio/mpeg\"  url='https://example.com/a.mp3' length=\"1\" />\
+            <description><![CDATA[<p>Hi</p>]]></description ></item></channel></rss >";
+        let reschedule = HashMap::from([("a".to_string(), parse_dt("2021-12-13T16:00:00"))]);
+        let options = RewriteOptions {
+            faithful: true,
+            ..Default::default()
+        };
+        let output = rewrite_feed(xml.as_bytes(), &reschedule, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let expected = xml
+            .replace(
+                "<pubDate>Sun, 29 Sep 2002 19:59:01 GMT</pubDate >",
+                "<pubDate>Mon, 13 Dec 2021 16:00:00 +0000</pubDate>",
+            )
+            .replace(
+                "<title >Caf&#xe9; &amp; Friends</title >",
+                "<title >Caf&#xe9; &amp; Friends (PodReplay)</title>",
+            );
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn podcasting20() {
+        let xml = include_str!("../tests/data/podcasting20.xml");
+        let reschedule = HashMap::from([
+            (
+                "example-episode-1".to_string(),
+                parse_dt("2022-01-15T16:00:00"),
+            ),
+            (
+                "example-episode-2".to_string(),
+                parse_dt("2022-01-22T16:00:00"),
+            ),
+        ]);
+        let output = rewrite_feed(xml.as_bytes(), &reschedule, &options(true, true, None)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let expected = xml
+            .replace(
+                "<channel>",
+                "<channel>\n        <itunes:block>Yes</itunes:block>\n        <podcast:locked>yes</podcast:locked>",
+            )
+            .replace(
+                "<podcast:locked owner=\"owner@example.com\">no</podcast:locked>",
+                "",
+            )
+            .replace(
+                "<pubDate>Fri, 09 Oct 2020 04:30:38 GMT</pubDate>",
+                "<pubDate>Sat, 22 Jan 2022 16:00:00 +0000</pubDate>",
+            )
+            .replace(
+                "<pubDate>Fri, 02 Oct 2020 04:30:38 GMT</pubDate>",
+                "<pubDate>Sat, 15 Jan 2022 16:00:00 +0000</pubDate>",
+            )
+            .replace(
+                "<title>Podcasting 2.0 Example</title>",
+                "<title>Podcasting 2.0 Example (PodReplay)</title>",
             );
         assert_eq!(output, expected);
     }
 
+    #[test]
+    fn streaming() {
+        let xml = include_str!("../tests/data/megaphone.xml");
+        let reschedule = HashMap::from([(
+            "613b2312-4f9c-11eb-a6af-b700e1b799da".to_string(),
+            parse_dt("2022-01-16T16:00:00"),
+        )]);
+        let options = options(true, true, Some("Hello World".to_string()));
+        let expected = rewrite_feed(xml.as_bytes(), &reschedule, &options).unwrap();
+        // A tiny buffer makes sure nothing relies on having the whole document.
+        let input = std::io::BufReader::with_capacity(16, xml.as_bytes());
+        let mut output = Vec::new();
+        rewrite_feed_to_writer(input, &mut output, &reschedule, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn lenient() {
+        let xml = include_str!("../tests/data/sample_rss_2.0.xml")
+            .replace("6:56:02PM</guid>", "6:56:02PM</gui>")
+            .replace(
+                "<title>Joshua Allen: Who loves namespaces?</title>",
+                "<title>Joshua&nbsp;Allen & Friends</title>",
+            );
+        let reschedule = HashMap::from([
+            (
+                "http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM"
+                    .to_string(),
+                parse_dt("2021-12-13T16:00:00"),
+            ),
+            (
+                "http://scriptingnews.userland.com/backissues/2002/09/29#When:12:59:01PM"
+                    .to_string(),
+                parse_dt("2021-12-20T16:00:00"),
+            ),
+        ]);
+        assert!(rewrite_feed(xml.as_bytes(), &reschedule, &options(true, false, None)).is_err());
+
+        let (output, repairs) =
+            rewrite_feed_lenient(xml.as_bytes(), &reschedule, &options(true, false, None)).unwrap();
+        let repairs: Vec<_> = repairs.into_iter().map(|r| r.kind).collect();
+        assert_eq!(
+            repairs,
+            vec![
+                RepairKind::ReplacedEntity,
+                RepairKind::EscapedAmpersand,
+                RepairKind::SkippedItem
+            ]
+        );
+        // Whatever we output should be good enough for the strict parser.
+        let summary = FeedSummary::new("testing".into(), &output).unwrap();
+        assert_eq!(summary.items.len(), 1);
+        assert_eq!(summary.items[0].title, "Joshua\u{a0}Allen & Friends");
+        assert_eq!(summary.items[0].timestamp, parse_dt("2021-12-20T16:00:00"));
+    }
+
+    #[test]
+    fn lenient_truncated() {
+        let xml = include_str!("../tests/data/sample_rss_2.0.xml");
+        let xml = &xml[..xml.find("fake_episode2.mp3").unwrap()];
+        let reschedule = HashMap::from([(
+            "http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM".to_string(),
+            parse_dt("2021-12-13T16:00:00"),
+        )]);
+        let (output, repairs) =
+            rewrite_feed_lenient(xml.as_bytes(), &reschedule, &options(false, false, None))
+                .unwrap();
+        assert_eq!(repairs.len(), 1);
+        assert_eq!(repairs[0].kind, RepairKind::TruncatedDocument);
+        assert!(String::from_utf8_lossy(&output).ends_with("</channel></rss>"));
+        let summary = FeedSummary::new("testing".into(), &output).unwrap();
+        assert_eq!(summary.items.len(), 1);
+    }
+
     // We don't explicitely support RSS 0.91 or 0.92 since they don't seem to have
     // an item level pubDate and I doubt they're really used for podcast feeds
     // these days. On the other hand, I'm not making any specific efforts to
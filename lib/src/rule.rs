@@ -55,7 +55,8 @@ impl IntoIterator for Rule {
 }
 
 impl Rule {
-    fn parse(start: DateTime<Utc>, s: &str) -> Result<Rule, String> {
+    /// See `parse_rule`, which falls back to weekly rather than failing.
+    pub fn parse(start: DateTime<Utc>, s: &str) -> Result<Rule, String> {
         use nom::{character::complete::one_of, sequence::tuple};
         tuple((interval, one_of("mwd"), weekdays))(s)
             .map(|(_, (interval, freq, days))| match freq {
@@ -0,0 +1,99 @@
+use chrono::{DateTime, Utc};
+
+use crate::reschedule::Reschedule;
+
+/// How far along a replay is, for telling subscribers about it with an item
+/// of its own at the top of the feed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayStatus {
+    pub replayed: usize,
+    pub total: usize,
+    pub next_slot: Option<DateTime<Utc>>,
+}
+
+impl ReplayStatus {
+    /// Takes what `reschedule_feed` returned, along with how many items there
+    /// are to replay in total.
+    pub fn new<K>(
+        reschedule: &Reschedule<K>,
+        next_slot: Option<DateTime<Utc>>,
+        total: usize,
+    ) -> Self {
+        ReplayStatus {
+            replayed: reschedule.len(),
+            total: total.max(reschedule.len()),
+            next_slot,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match self.next_slot {
+            Some(next_slot) => format!("Next episode on {}", format_date(&next_slot)),
+            // Nothing left to replay, so anything new is passed along as is.
+            None => "Replay complete, now following live".to_string(),
+        }
+    }
+
+    pub fn description(&self) -> String {
+        let ReplayStatus {
+            replayed, total, ..
+        } = self;
+        match self.next_slot {
+            Some(next_slot) => format!(
+                "{replayed} of {total} episodes replayed. The next one will be out on {}.",
+                format_date(&next_slot)
+            ),
+            None => format!(
+                "All {total} episodes have been replayed. New episodes will show up as they're published."
+            ),
+        }
+    }
+
+    /// Changes whenever there's something new to say, so apps that only look
+    /// at new ids don't miss it.
+    pub fn id(&self) -> String {
+        let next_slot = self
+            .next_slot
+            .map_or("complete".to_string(), |slot| slot.timestamp().to_string());
+        format!("podreplay-status-{}-{next_slot}", self.replayed)
+    }
+}
+
+fn format_date(date: &DateTime<Utc>) -> String {
+    date.format("%A, %B %-d, %Y").to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::ReplayStatus;
+    use crate::test_helpers::parse_dt;
+
+    #[test]
+    fn in_progress() {
+        let reschedule = HashMap::from([("a", parse_dt("2021-12-13T16:00:00"))]);
+        let status = ReplayStatus::new(&reschedule, Some(parse_dt("2021-12-20T16:00:00")), 3);
+        assert_eq!(status.title(), "Next episode on Monday, December 20, 2021");
+        assert_eq!(
+            status.description(),
+            "1 of 3 episodes replayed. The next one will be out on Monday, December 20, 2021."
+        );
+        assert_eq!(status.id(), "podreplay-status-1-1640016000");
+    }
+
+    #[test]
+    fn complete() {
+        let reschedule = HashMap::from([
+            ("a", parse_dt("2021-12-13T16:00:00")),
+            ("b", parse_dt("2021-12-20T16:00:00")),
+        ]);
+        let status = ReplayStatus::new(&reschedule, None, 2);
+        assert_eq!(status.title(), "Replay complete, now following live");
+        assert_eq!(
+            status.description(),
+            "All 2 episodes have been replayed. New episodes will show up as they're published."
+        );
+        assert_eq!(status.id(), "podreplay-status-2-complete");
+    }
+}
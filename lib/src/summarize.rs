@@ -9,7 +9,11 @@ use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, io::BufRead};
 use thiserror::Error;
 
-use crate::CachedEntry;
+use crate::{
+    error::ParseError,
+    repair::{with_recovery, Recoverable, Repair},
+    CachedEntry,
+};
 
 #[derive(Debug)]
 struct PartialItem<'a> {
@@ -17,7 +21,12 @@ struct PartialItem<'a> {
     id: Option<String>,
     title: Option<String>,
     timestamp: Option<DateTime<Utc>>,
+    link: Option<String>,
     had_enclosure: bool,
+    season: Option<u32>,
+    episode: Option<String>,
+    chapters: Option<Chapters>,
+    transcripts: Vec<Transcript>,
 }
 
 impl<'a> PartialItem<'a> {
@@ -27,18 +36,99 @@ impl<'a> PartialItem<'a> {
                 title: self.title?,
                 id: self.id?,
                 timestamp: self.timestamp?,
+                link: self.link,
+                season: self.season,
+                episode: self.episode,
+                chapters: self.chapters,
+                transcripts: self.transcripts,
             })
         } else {
             None
         }
     }
+
+    /// Atom's `<link href="…">`, which is the item's web page unless it says
+    /// it's something else.
+    fn add_alternate_link(&mut self, element: &BytesStart) {
+        let rel = get_attribute(element, "rel");
+        if self.link.is_none() && matches!(rel.as_deref(), None | Some("alternate")) {
+            self.link = get_attribute(element, "href");
+        }
+    }
+
+    fn add_podcast_link(&mut self, element: &BytesStart) {
+        match element.name() {
+            QName(b"podcast:chapters") => {
+                if let Some(chapters) = Chapters::from_element(element) {
+                    self.chapters = Some(chapters);
+                }
+            }
+            QName(b"podcast:transcript") => {
+                if let Some(transcript) = Transcript::from_element(element) {
+                    self.transcripts.push(transcript);
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
 pub struct SummaryItem {
     pub id: String,
     pub title: String,
     pub timestamp: DateTime<Utc>,
+    /// The item's web page, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub season: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub episode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chapters: Option<Chapters>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transcripts: Vec<Transcript>,
+}
+
+/// A `<podcast:chapters>` link from the Podcasting 2.0 namespace.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Chapters {
+    pub url: String,
+    #[serde(rename = "type")]
+    pub mime_type: String,
+}
+
+impl Chapters {
+    fn from_element(element: &BytesStart) -> Option<Self> {
+        Some(Chapters {
+            url: get_attribute(element, "url")?,
+            mime_type: get_attribute(element, "type")?,
+        })
+    }
+}
+
+/// A `<podcast:transcript>` link from the Podcasting 2.0 namespace.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Transcript {
+    pub url: String,
+    #[serde(rename = "type")]
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rel: Option<String>,
+}
+
+impl Transcript {
+    fn from_element(element: &BytesStart) -> Option<Self> {
+        Some(Transcript {
+            url: get_attribute(element, "url")?,
+            mime_type: get_attribute(element, "type")?,
+            language: get_attribute(element, "language"),
+            rel: get_attribute(element, "rel"),
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -47,33 +137,91 @@ pub struct FeedSummary {
     pub title: String,
     #[serde(skip_serializing)]
     pub marked_private: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub podcast_guid: Option<String>,
+    #[serde(skip_serializing)]
+    pub locked: bool,
+    /// Where the publisher says the feed has moved to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_feed_url: Option<String>,
     pub items: Vec<SummaryItem>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repairs: Vec<Repair>,
+}
+
+#[derive(Debug, Default)]
+struct ChannelSummary {
+    title: Option<String>,
+    marked_private: bool,
+    podcast_guid: Option<String>,
+    locked: bool,
+    new_feed_url: Option<String>,
 }
 
 #[derive(Error, Debug)]
 pub enum SummarizeError {
     #[error("Failed to parse feed: {0}")]
-    Parse(#[from] quick_xml::Error),
-    // #[error("Unexpected")]
-    // Parse(#[from] quick_xml::Error),
+    Parse(#[from] Box<ParseError>),
     #[error("No valid feed found")]
     NotAFeed,
 }
 
+impl SummarizeError {
+    pub fn parse_error(&self) -> Option<&ParseError> {
+        match self {
+            SummarizeError::Parse(err) => Some(err),
+            SummarizeError::NotAFeed => None,
+        }
+    }
+
+    /// See `ParseError::with_context`.
+    pub fn with_context(self, xml: &[u8]) -> Self {
+        match self {
+            SummarizeError::Parse(err) => SummarizeError::Parse(Box::new(err.with_context(xml))),
+            err => err,
+        }
+    }
+}
+
+impl Recoverable for SummarizeError {
+    fn parse_error(&self) -> Option<&ParseError> {
+        self.parse_error()
+    }
+}
+
 impl FeedSummary {
-    pub fn new(uri: String, reader: &[u8]) -> Result<Self, SummarizeError> {
-        let reader = quick_xml::Reader::from_reader(reader);
-        let (mut items, title, marked_private) = summarize_feed(reader)?;
+    pub fn new(uri: String, xml: &[u8]) -> Result<Self, SummarizeError> {
+        Self::from_reader(uri, xml).map_err(|err| err.with_context(xml))
+    }
+
+    /// Summarizes a feed without needing the whole thing in memory. Since we
+    /// can't look back through the document, any parse error will only have
+    /// its position filled in (see `SummarizeError::with_context`).
+    pub fn from_reader<R: BufRead>(uri: String, reader: R) -> Result<Self, SummarizeError> {
+        let (mut items, channel) = summarize_feed(reader)?;
         items.reverse(); // we're most likely in reverse order
         items.sort_unstable_by_key(|i| i.timestamp); // just to be safe
         Ok(FeedSummary {
             uri,
-            title: title.unwrap_or_default(),
-            marked_private,
+            title: channel.title.unwrap_or_default(),
+            marked_private: channel.marked_private,
+            podcast_guid: channel.podcast_guid,
+            locked: channel.locked,
+            new_feed_url: channel.new_feed_url,
             items,
+            repairs: Vec::new(),
         })
     }
 
+    /// Like `new`, but fixes what it can (stray ampersands, HTML entities,
+    /// unescaped HTML) and drops items it can't parse instead of failing the
+    /// whole feed. Anything we had to do is listed in `repairs`.
+    pub fn new_lenient(uri: String, xml: &[u8]) -> Result<Self, SummarizeError> {
+        let (mut summary, repairs) = with_recovery(xml, |xml| Self::new(uri.clone(), xml))?;
+        summary.repairs = repairs;
+        Ok(summary)
+    }
+
     pub fn id_map(&self) -> HashMap<&str, &SummaryItem> {
         self.items.iter().map(|e| (e.id.as_str(), e)).collect()
     }
@@ -99,15 +247,18 @@ impl FeedSummary {
     }
 }
 
-pub fn summarize_feed(
-    mut reader: quick_xml::Reader<&[u8]>,
-) -> Result<(Vec<SummaryItem>, Option<String>, bool), SummarizeError> {
+fn summarize_feed<R: BufRead>(
+    reader: R,
+) -> Result<(Vec<SummaryItem>, ChannelSummary), SummarizeError> {
+    let mut reader = quick_xml::Reader::from_reader(reader);
+    let parse_error = |reader: &quick_xml::Reader<R>, err| {
+        SummarizeError::Parse(Box::new(ParseError::at(reader.buffer_position(), err)))
+    };
     let mut results: Vec<SummaryItem> = Vec::new();
     let mut buf: Vec<u8> = Vec::new();
     let mut partial_item: Option<PartialItem> = None;
     let mut xml_decl_found = false;
-    let mut feed_title = None;
-    let mut marked_private = false;
+    let mut channel = ChannelSummary::default();
 
     loop {
         match reader.read_event_into(&mut buf) {
@@ -122,12 +273,19 @@ pub fn summarize_feed(
                         id: None,
                         title: None,
                         timestamp: None,
+                        link: None,
                         had_enclosure: false,
+                        season: None,
+                        episode: None,
+                        chapters: None,
+                        transcripts: Vec::new(),
                     });
                 }
                 QName(b"guid") | QName(b"id") => {
                     if let Some(item) = &mut partial_item {
-                        item.id = Some(read_contents(&mut reader, &start)?);
+                        let id = read_contents(&mut reader, &start)
+                            .map_err(|err| parse_error(&reader, err))?;
+                        item.id = Some(id);
                     }
                 }
                 QName(b"title") => {
@@ -135,7 +293,7 @@ pub fn summarize_feed(
                         if let Some(item) = &mut partial_item {
                             item.title = Some(title);
                         } else {
-                            feed_title = Some(title);
+                            channel.title = Some(title);
                         }
                     }
                 }
@@ -143,7 +301,7 @@ pub fn summarize_feed(
                     if let Some(item) = &mut partial_item {
                         if item.title.is_none() {
                             let name = start.name().to_owned();
-                            if let Ok(description) = reader.read_text(name) {
+                            if let Ok(description) = read_raw_text(&mut reader, name) {
                                 let text = html_to_text(&description);
                                 let title = if text.len() > 100 {
                                     format!("{}...", text.chars().take(90).collect::<String>())
@@ -158,8 +316,7 @@ pub fn summarize_feed(
                 QName(b"pubDate") | QName(b"updated") => {
                     if let Some(item) = &mut partial_item {
                         let name = start.name().to_owned();
-                        if let Some(timestamp) = reader
-                            .read_text(name)
+                        if let Some(timestamp) = read_raw_text(&mut reader, name)
                             .ok()
                             .and_then(|s| parse_timestamp(&s))
                         {
@@ -169,14 +326,52 @@ pub fn summarize_feed(
                 }
                 QName(b"itunes:block") if partial_item.is_none() => {
                     let name = start.name().to_owned();
-                    if let Ok(block) = reader.read_text(name) {
-                        marked_private = block.to_ascii_lowercase() == "yes"
+                    if let Ok(block) = read_raw_text(&mut reader, name) {
+                        channel.marked_private = block.to_ascii_lowercase() == "yes"
                     }
                 }
-                QName(b"enclosure") | QName(b"link") => {
-                    if is_audio_enclosure(&start) {
-                        if let Some(item) = &mut partial_item {
-                            item.had_enclosure = true;
+                QName(b"podcast:guid") if partial_item.is_none() => {
+                    channel.podcast_guid = read_contents(&mut reader, &start).ok();
+                }
+                QName(b"itunes:new-feed-url") if partial_item.is_none() => {
+                    channel.new_feed_url = read_contents(&mut reader, &start)
+                        .ok()
+                        .map(|url| url.trim().to_string())
+                        .filter(|url| !url.is_empty());
+                }
+                QName(b"podcast:locked") if partial_item.is_none() => {
+                    if let Ok(locked) = read_contents(&mut reader, &start) {
+                        channel.locked = locked.eq_ignore_ascii_case("yes");
+                    }
+                }
+                QName(b"podcast:season") => {
+                    if let Some(item) = &mut partial_item {
+                        item.season = read_contents(&mut reader, &start)
+                            .ok()
+                            .and_then(|s| s.parse().ok());
+                    }
+                }
+                QName(b"podcast:episode") => {
+                    if let Some(item) = &mut partial_item {
+                        item.episode = read_contents(&mut reader, &start).ok();
+                    }
+                }
+                QName(b"podcast:chapters") | QName(b"podcast:transcript") => {
+                    if let Some(item) = &mut partial_item {
+                        item.add_podcast_link(&start);
+                    }
+                }
+                QName(b"enclosure") | QName(b"link") if is_audio_enclosure(&start) => {
+                    if let Some(item) = &mut partial_item {
+                        item.had_enclosure = true;
+                    }
+                }
+                QName(b"link") => {
+                    if let Some(item) = &mut partial_item {
+                        if start.try_get_attribute("href").ok().flatten().is_some() {
+                            item.add_alternate_link(&start);
+                        } else if item.link.is_none() {
+                            item.link = read_contents(&mut reader, &start).ok();
                         }
                     }
                 }
@@ -188,6 +383,16 @@ pub fn summarize_feed(
                         item.had_enclosure = true;
                     }
                 }
+                QName(b"podcast:chapters") | QName(b"podcast:transcript") => {
+                    if let Some(item) = &mut partial_item {
+                        item.add_podcast_link(&empty);
+                    }
+                }
+                QName(b"link") => {
+                    if let Some(item) = &mut partial_item {
+                        item.add_alternate_link(&empty);
+                    }
+                }
                 _ => {}
             },
             Ok(Event::End(end)) => {
@@ -204,7 +409,7 @@ pub fn summarize_feed(
             }
             Err(e) => {
                 tracing::error!("Error at position {}: {:?}", reader.buffer_position(), e);
-                return Err(e.into());
+                return Err(parse_error(&reader, e));
             }
         }
         buf.clear();
@@ -212,7 +417,7 @@ pub fn summarize_feed(
     if results.is_empty() && !xml_decl_found {
         Err(SummarizeError::NotAFeed)
     } else {
-        Ok((results, feed_title, marked_private))
+        Ok((results, channel))
     }
 }
 
@@ -233,14 +438,31 @@ pub fn is_audio_enclosure(start: &BytesStart) -> bool {
     }
 }
 
+fn get_attribute(element: &BytesStart, key: &str) -> Option<String> {
+    let attr = element.try_get_attribute(key).ok()??;
+    attr.unescape_value().ok().map(|value| value.into_owned())
+}
+
 pub fn read_contents<R: BufRead>(
     reader: &mut quick_xml::Reader<R>,
     start: &BytesStart,
+) -> Result<String, quick_xml::Error> {
+    read_contents_into(reader, start, &mut Vec::new())
+}
+
+/// Like `read_contents`, but also keeps the events it read (up to and
+/// including the end tag) so they can be written back out as they were.
+pub fn read_contents_into<R: BufRead>(
+    reader: &mut quick_xml::Reader<R>,
+    start: &BytesStart,
+    events: &mut Vec<Event<'static>>,
 ) -> Result<String, quick_xml::Error> {
     let mut id_buf: Vec<u8> = Vec::new();
     let mut id = String::new();
     loop {
-        match reader.read_event_into(&mut id_buf)? {
+        let ev = reader.read_event_into(&mut id_buf)?;
+        events.push(ev.clone().into_owned());
+        match ev {
             Event::Text(bytes) => {
                 if let Ok(frag) = bytes.unescape() {
                     id.push_str(frag.trim());
@@ -268,6 +490,48 @@ pub fn read_contents<R: BufRead>(
     }
 }
 
+/// The raw (still escaped) contents of an element, like `Reader::read_text`
+/// but for any reader rather than just slices. Anything nested is written back
+/// out more or less as it was.
+pub fn read_raw_text<R: BufRead>(
+    reader: &mut quick_xml::Reader<R>,
+    end: QName,
+) -> Result<String, quick_xml::Error> {
+    let mut buf = Vec::new();
+    let mut text = Vec::new();
+    let mut depth = 0;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::End(e) if e.name() == end && depth == 0 => break,
+            Event::Start(e) => {
+                if e.name() == end {
+                    depth += 1;
+                }
+                text.extend_from_slice(&[b"<", e.as_ref(), b">"].concat());
+            }
+            Event::End(e) => {
+                if e.name() == end {
+                    depth -= 1;
+                }
+                text.extend_from_slice(&[b"</", e.as_ref(), b">"].concat());
+            }
+            Event::Empty(e) => text.extend_from_slice(&[b"<", e.as_ref(), b"/>"].concat()),
+            Event::Text(e) => text.extend_from_slice(&e),
+            Event::CData(e) => text.extend_from_slice(&[b"<![CDATA[", e.as_ref(), b"]]>"].concat()),
+            Event::Comment(e) => text.extend_from_slice(&[b"<!--", e.as_ref(), b"-->"].concat()),
+            Event::Eof => {
+                return Err(quick_xml::Error::UnexpectedEof(format!(
+                    "while attempting to read {:?}",
+                    end
+                )))
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+    String::from_utf8(text).map_err(|err| quick_xml::Error::NonDecodable(Some(err.utf8_error())))
+}
+
 pub fn parse_timestamp(timestamp_str: &str) -> Option<DateTime<Utc>> {
     parse_date(timestamp_str).map(|ts| ts.into())
 }
@@ -279,8 +543,8 @@ fn html_to_text(html: &str) -> String {
 
 #[cfg(test)]
 mod test {
-    use super::{FeedSummary, SummaryItem};
-    use crate::test_helpers::parse_dt;
+    use super::{Chapters, FeedSummary, SummaryItem, Transcript};
+    use crate::{test_helpers::parse_dt, RepairKind};
     use pretty_assertions::assert_eq;
 
     #[test]
@@ -291,6 +555,8 @@ mod test {
             id: "urn:uuid:1225c695-cfb8-4ebb-aaaa-80da344efa6a".to_string(),
             title: "Atom-Powered Robots Run Amok".to_string(),
             timestamp: parse_dt("2003-12-13T18:30:02"),
+            link: Some("http://example.org/2003/12/13/atom03".to_string()),
+            ..Default::default()
         }];
         assert_eq!(output.items, expected);
     }
@@ -305,6 +571,7 @@ mod test {
                     .to_string(),
                 title: "Joshua Allen: Who loves namespaces?".to_string(),
                 timestamp: parse_dt("2002-09-29T19:59:01"),
+                ..Default::default()
             },
             SummaryItem {
                 id: "http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM"
@@ -312,6 +579,7 @@ mod test {
                 title: "With any luck we should have one or two more days of namespaces stuff here on Scripting Ne..."
                     .to_string(),
                 timestamp: parse_dt("2002-09-30T01:56:02"),
+                ..Default::default()
             },
         ];
         assert_eq!(output.items, expected);
@@ -326,18 +594,136 @@ mod test {
                 id: "612990fc-4f9c-11eb-a6af-e7830eb4fc55".to_string(),
                 title: "S6 Ep. 6: No Peace".to_string(),
                 timestamp: parse_dt("2021-12-15T08:00:00"),
+                ..Default::default()
             },
             SummaryItem {
                 id: "613b2312-4f9c-11eb-a6af-b700e1b799da".to_string(),
                 title: "S6 Ep. 7: Into Ashes".to_string(),
                 timestamp: parse_dt("2021-12-22T08:00:00"),
+                ..Default::default()
             },
             SummaryItem {
                 id: "614f5f12-4f9c-11eb-a6af-cb9557e04485".to_string(),
                 title: "S6 Ep. 8: Damages".to_string(),
                 timestamp: parse_dt("2021-12-29T08:00:00"),
+                ..Default::default()
             },
         ];
         assert_eq!(output.items, expected);
     }
+
+    #[test]
+    fn podcasting20() {
+        let xml = include_bytes!("../tests/data/podcasting20.xml");
+        let output = FeedSummary::new("testing".into(), xml).unwrap();
+        assert_eq!(
+            output.podcast_guid.as_deref(),
+            Some("917393e3-1b1e-5cef-ace4-edaa54e1f810")
+        );
+        assert!(!output.locked);
+        let expected = vec![
+            SummaryItem {
+                id: "example-episode-1".to_string(),
+                title: "Episode 1".to_string(),
+                timestamp: parse_dt("2020-10-02T04:30:38"),
+                season: Some(1),
+                episode: Some("1".to_string()),
+                ..Default::default()
+            },
+            SummaryItem {
+                id: "example-episode-2".to_string(),
+                title: "Episode 2".to_string(),
+                timestamp: parse_dt("2020-10-09T04:30:38"),
+                link: Some("https://example.com/episode2".to_string()),
+                season: Some(1),
+                episode: Some("2.5".to_string()),
+                chapters: Some(Chapters {
+                    url: "https://example.com/episode2/chapters.json".to_string(),
+                    mime_type: "application/json+chapters".to_string(),
+                }),
+                transcripts: vec![
+                    Transcript {
+                        url: "https://example.com/episode2/transcript.vtt".to_string(),
+                        mime_type: "text/vtt".to_string(),
+                        language: None,
+                        rel: None,
+                    },
+                    Transcript {
+                        url: "https://example.com/episode2/transcript.srt".to_string(),
+                        mime_type: "application/srt".to_string(),
+                        language: Some("es".to_string()),
+                        rel: Some("captions".to_string()),
+                    },
+                ],
+            },
+        ];
+        assert_eq!(output.items, expected);
+    }
+
+    #[test]
+    fn new_feed_url() {
+        let xml = include_str!("../tests/data/sample_rss_2.0.xml");
+        let output = FeedSummary::new("testing".into(), xml.as_bytes()).unwrap();
+        assert_eq!(output.new_feed_url, None);
+
+        let xml = xml.replace(
+            "<channel>",
+            "<channel><itunes:new-feed-url> https://example.com/moved </itunes:new-feed-url>",
+        );
+        let output = FeedSummary::new("testing".into(), xml.as_bytes()).unwrap();
+        assert_eq!(
+            output.new_feed_url.as_deref(),
+            Some("https://example.com/moved")
+        );
+    }
+
+    #[test]
+    fn from_reader() {
+        let xml = include_bytes!("../tests/data/megaphone.xml");
+        let expected = FeedSummary::new("testing".into(), xml).unwrap();
+        let input = std::io::BufReader::with_capacity(16, &xml[..]);
+        let output = FeedSummary::from_reader("testing".into(), input).unwrap();
+        assert_eq!(output.items, expected.items);
+        assert_eq!(output.title, expected.title);
+    }
+
+    #[test]
+    fn from_reader_errors() {
+        let xml = include_str!("../tests/data/sample_rss_2.0.xml").replace("</guid>", "</gui>");
+        let err = FeedSummary::from_reader("testing".into(), xml.as_bytes()).unwrap_err();
+        assert_eq!(err.parse_error().unwrap().line, 0);
+        let err = err.with_context(xml.as_bytes());
+        assert_eq!(err.parse_error().unwrap().line, 26);
+        assert_eq!(err.parse_error().unwrap().path, "rss/channel/item[1]/guid");
+    }
+
+    #[test]
+    fn lenient() {
+        let xml = include_str!("../tests/data/sample_rss_2.0.xml")
+            .replace("6:56:02PM</guid>", "6:56:02PM</gui>")
+            .replace(
+                "<title>Joshua Allen: Who loves namespaces?</title>",
+                "<title>Joshua&nbsp;Allen & Friends</title>",
+            );
+        assert!(FeedSummary::new("testing".into(), xml.as_bytes()).is_err());
+
+        let output = FeedSummary::new_lenient("testing".into(), xml.as_bytes()).unwrap();
+        let expected = vec![SummaryItem {
+            id: "http://scriptingnews.userland.com/backissues/2002/09/29#When:12:59:01PM"
+                .to_string(),
+            title: "Joshua\u{a0}Allen & Friends".to_string(),
+            timestamp: parse_dt("2002-09-29T19:59:01"),
+            ..Default::default()
+        }];
+        assert_eq!(output.items, expected);
+        let repairs: Vec<_> = output.repairs.iter().map(|r| (r.kind, r.line)).collect();
+        assert_eq!(
+            repairs,
+            vec![
+                (RepairKind::ReplacedEntity, 31),
+                (RepairKind::EscapedAmpersand, 31),
+                (RepairKind::SkippedItem, 26),
+            ]
+        );
+    }
 }
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+use crate::reschedule::Reschedule;
+use crate::summarize::FeedSummary;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("Unknown placeholder {{{0}}}")]
+    UnknownPlaceholder(String),
+    #[error("Invalid date format {0:?}")]
+    InvalidDateFormat(String),
+    #[error("Unmatched {0:?} (use {0}{0} for a literal one)")]
+    Unmatched(char),
+}
+
+/// A title to give each replayed item, such as
+/// `"{n}/{total} — {title} (orig. {original_date:%Y-%m-%d})"`.
+///
+/// - `{n}` is the item's place in the feed, counting from the oldest
+/// - `{total}` is how many items the feed has
+/// - `{title}` is the item's original title
+/// - `{original_date}` is when it was first published
+/// - `{date}` is when it was replayed
+///
+/// Dates use `%Y-%m-%d` unless given a `strftime` style format after a `:`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitleTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Number,
+    Total,
+    Title,
+    OriginalDate(String),
+    Date(String),
+}
+
+impl TitleTemplate {
+    pub fn parse(template: &str) -> Result<Self, TemplateError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' | '}' if chars.as_str().starts_with(c) => {
+                    chars.next();
+                    literal.push(c);
+                }
+                '}' => return Err(TemplateError::Unmatched('}')),
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or(TemplateError::Unmatched('{'))?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(parse_placeholder(&rest[..end])?);
+                    chars = rest[end + 1..].chars();
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(TitleTemplate { parts })
+    }
+
+    /// New titles for each of the replayed items, by id. Items that haven't
+    /// been replayed are left out.
+    pub fn render_titles(
+        &self,
+        summary: &FeedSummary,
+        reschedule: &Reschedule<String>,
+    ) -> HashMap<String, String> {
+        let total = summary.items.len();
+        summary
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, item)| {
+                let replayed_at = reschedule.get(&item.id)?;
+                let title =
+                    self.render(index + 1, total, &item.title, &item.timestamp, replayed_at);
+                Some((item.id.clone(), title))
+            })
+            .collect()
+    }
+
+    fn render(
+        &self,
+        n: usize,
+        total: usize,
+        title: &str,
+        original_date: &DateTime<Utc>,
+        date: &DateTime<Utc>,
+    ) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Number => n.to_string(),
+                Part::Total => total.to_string(),
+                Part::Title => title.to_string(),
+                Part::OriginalDate(format) => original_date.format(format).to_string(),
+                Part::Date(format) => date.format(format).to_string(),
+            })
+            .collect()
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Part, TemplateError> {
+    let (name, format) = match placeholder.split_once(':') {
+        Some((name, format)) => (name, Some(format)),
+        None => (placeholder, None),
+    };
+    let date_format = || {
+        let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+        // Checked now, since formatting with a bad one panics.
+        if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+            return Err(TemplateError::InvalidDateFormat(format.to_string()));
+        }
+        Ok(format.to_string())
+    };
+    match (name.trim(), format) {
+        ("n", None) => Ok(Part::Number),
+        ("total", None) => Ok(Part::Total),
+        ("title", None) => Ok(Part::Title),
+        ("original_date", _) => Ok(Part::OriginalDate(date_format()?)),
+        ("date", _) => Ok(Part::Date(date_format()?)),
+        _ => Err(TemplateError::UnknownPlaceholder(placeholder.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{TemplateError, TitleTemplate};
+    use crate::{test_helpers::parse_dt, FeedSummary};
+
+    #[test]
+    fn renders_replayed_items() {
+        let xml = include_str!("../tests/data/sample_rss_2.0.xml");
+        let summary = FeedSummary::new("testing".into(), xml.as_bytes()).unwrap();
+        let reschedule = HashMap::from([(
+            "http://scriptingnews.userland.com/backissues/2002/09/29#When:12:59:01PM".to_string(),
+            parse_dt("2021-12-13T16:00:00"),
+        )]);
+        let template = TitleTemplate::parse(
+            "{n}/{total} — {title} (orig. {original_date:%b %-d, %Y}, {date})",
+        )
+        .unwrap();
+        let titles = template.render_titles(&summary, &reschedule);
+        assert_eq!(
+            titles,
+            HashMap::from([(
+                "http://scriptingnews.userland.com/backissues/2002/09/29#When:12:59:01PM"
+                    .to_string(),
+                "1/2 — Joshua Allen: Who loves namespaces? (orig. Sep 29, 2002, 2021-12-13)"
+                    .to_string()
+            )])
+        );
+    }
+
+    #[test]
+    fn escaped_braces() {
+        let template = TitleTemplate::parse("{{{n}}} {title}").unwrap();
+        let date = parse_dt("2021-12-13T16:00:00");
+        assert_eq!(template.render(3, 5, "Hi", &date, &date), "{3} Hi");
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert_eq!(
+            TitleTemplate::parse("{episode}"),
+            Err(TemplateError::UnknownPlaceholder("episode".to_string()))
+        );
+        assert_eq!(
+            TitleTemplate::parse("{title"),
+            Err(TemplateError::Unmatched('{'))
+        );
+        assert_eq!(
+            TitleTemplate::parse("title}"),
+            Err(TemplateError::Unmatched('}'))
+        );
+        assert_eq!(
+            TitleTemplate::parse("{date:%Q}"),
+            Err(TemplateError::InvalidDateFormat("%Q".to_string()))
+        );
+        assert!(TitleTemplate::parse("{n:5}").is_err());
+    }
+}
@@ -4,7 +4,7 @@
 mod utils;
 
 use chrono::{DateTime, TimeZone, Utc};
-use podreplay_lib::{parse_rule, reschedule_feed, Item};
+use podreplay_lib::{parse_rule, plan_feed, reschedule_feed, Item};
 use wasm_bindgen::prelude::*;
 
 fn dt_from_unix_epoch(seconds: f64) -> DateTime<Utc> {
@@ -30,6 +30,17 @@ impl Item<usize> for TinyItem {
     }
 }
 
+fn tiny_items(timestamps: &[f64]) -> Vec<TinyItem> {
+    timestamps
+        .iter()
+        .enumerate()
+        .map(|(index, timestamp)| TinyItem {
+            id: index,
+            timestamp: dt_from_unix_epoch(*timestamp),
+        })
+        .collect()
+}
+
 #[wasm_bindgen]
 pub fn reschedule(
     timestamps: &[f64],
@@ -42,14 +53,7 @@ pub fn reschedule(
     utils::set_panic_hook();
 
     let length = timestamps.len();
-    let items: Vec<_> = timestamps
-        .iter()
-        .enumerate()
-        .map(|(index, timestamp)| TinyItem {
-            id: index,
-            timestamp: dt_from_unix_epoch(*timestamp),
-        })
-        .collect();
+    let items = tiny_items(timestamps);
     let start = dt_from_unix_epoch(start);
     let rule = parse_rule(start, rule);
     let first = first.map(dt_from_unix_epoch);
@@ -64,3 +68,67 @@ pub fn reschedule(
         })
         .collect()
 }
+
+/// The order the items will be replayed in, by their index in `timestamps`.
+/// Everything from `first_projected` on assumes nothing else is published
+/// before then, so it's subject to change.
+#[wasm_bindgen]
+pub struct Plan {
+    order: Vec<u32>,
+    dates: Vec<f64>,
+    pub first_projected: u32,
+    pub next_slot: Option<f64>,
+}
+
+#[wasm_bindgen]
+impl Plan {
+    pub fn order(&self) -> Vec<u32> {
+        self.order.clone()
+    }
+
+    /// When each item in `order` will be replayed.
+    pub fn dates(&self) -> Vec<f64> {
+        self.dates.clone()
+    }
+}
+
+#[wasm_bindgen]
+pub fn plan(
+    timestamps: &[f64],
+    rule: &str,
+    start: f64,
+    now: f64,
+    first: Option<f64>,
+    last: Option<f64>,
+) -> Plan {
+    #[cfg(debug_assertions)]
+    utils::set_panic_hook();
+
+    let items = tiny_items(timestamps);
+    let start = dt_from_unix_epoch(start);
+    let rule = parse_rule(start, rule);
+    let first = first.map(dt_from_unix_epoch);
+    let last = last.map(dt_from_unix_epoch);
+
+    let plan = plan_feed(
+        &items,
+        rule,
+        start,
+        dt_from_unix_epoch(now),
+        None,
+        first,
+        last,
+    );
+
+    let first_projected = plan.items.iter().filter(|item| !item.projected).count();
+    Plan {
+        order: plan.items.iter().map(|item| item.id as u32).collect(),
+        dates: plan
+            .items
+            .iter()
+            .map(|item| item.date.timestamp() as f64)
+            .collect(),
+        first_projected: first_projected as u32,
+        next_slot: plan.next_slot.map(|slot| slot.timestamp() as f64),
+    }
+}
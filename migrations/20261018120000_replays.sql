@@ -0,0 +1,12 @@
+CREATE TABLE replays (
+    slug TEXT NOT NULL PRIMARY KEY,
+    edit_token_hash TEXT NOT NULL,
+    uri TEXT NOT NULL,
+    rule TEXT NOT NULL,
+    start TEXT NOT NULL,
+    first DATETIME UTC,
+    last DATETIME UTC,
+    title TEXT,
+    created DATETIME UTC NOT NULL,
+    updated DATETIME UTC NOT NULL
+);
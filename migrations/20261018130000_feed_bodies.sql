@@ -0,0 +1,8 @@
+CREATE TABLE feed_bodies (
+    feed_id INTEGER NOT NULL PRIMARY KEY,
+    fetched DATETIME UTC NOT NULL,
+    etag TEXT,
+    content_type TEXT,
+    body BLOB NOT NULL,
+    FOREIGN KEY (feed_id) REFERENCES feeds (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
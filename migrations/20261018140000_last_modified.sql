@@ -0,0 +1,2 @@
+ALTER TABLE feeds ADD COLUMN last_modified TEXT;
+ALTER TABLE feed_bodies ADD COLUMN last_modified TEXT;
@@ -0,0 +1,15 @@
+CREATE TABLE feed_aliases (
+    uri TEXT NOT NULL PRIMARY KEY,
+    feed_id INTEGER NOT NULL,
+    added DATETIME UTC NOT NULL,
+    FOREIGN KEY (feed_id) REFERENCES feeds (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+CREATE INDEX feed_aliases_feed_id on feed_aliases (feed_id);
+
+CREATE TABLE unconfirmed_moves (
+    feed_id INTEGER NOT NULL PRIMARY KEY,
+    uri TEXT NOT NULL,
+    etag TEXT,
+    checked DATETIME UTC NOT NULL,
+    FOREIGN KEY (feed_id) REFERENCES feeds (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
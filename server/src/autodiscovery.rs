@@ -5,21 +5,22 @@ use itertools::Itertools;
 use kuchiki::{parse_html, traits::TendrilSink};
 use lazy_static::lazy_static;
 use percent_encoding::percent_decode_str;
-use podreplay_lib::FeedSummary;
+use podreplay_lib::{FeedSummary, ParseError, SummarizeError};
 use regex::Regex;
 use serde::Deserialize;
 use std::{
     fmt::Display,
     hash::Hash,
-    io::{BufRead, Cursor},
+    io::{BufRead, BufReader, Seek, SeekFrom},
     str::from_utf8,
 };
 use thiserror::Error;
 use url::Url;
 
 use crate::{
-    fetch::{FetchException, Fetched, HttpClient},
+    fetch::{FetchException, HttpClient, Spooled},
     helpers::MyIterUtils,
+    replay::{summarize_spooled, SpooledError},
 };
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
@@ -61,8 +62,12 @@ pub enum AutodiscoveryException {
     Fetch(#[from] FetchException),
     #[error("Unexpected internal error")]
     Io(#[from] std::io::Error),
+    #[error("Unexpected internal error")]
+    Task(#[from] tokio::task::JoinError),
     #[error("Autodiscovery failed")]
     Failed,
+    #[error("{0}")]
+    Parse(Box<ParseError>),
 }
 
 impl FeedUrl {
@@ -84,15 +89,15 @@ impl FeedUrl {
         &self,
         client: &HttpClient,
         etag: Option<String>,
-    ) -> Result<Fetched, FetchException> {
+    ) -> Result<Spooled, FetchException> {
         match self {
             FeedUrl::Unknown(url) | FeedUrl::GoogleLink(url) => {
-                client.get(url.as_str(), etag).await
+                client.get_spooled(url.as_str(), etag, None).await
             }
             FeedUrl::ApplePodcastId(id) => {
                 let api_url =
                     format!("https://itunes.apple.com/lookup?media=podcast&entity=podcast&id={id}");
-                let api_resp = client.get(&api_url, None).await?;
+                let api_resp = client.get(&api_url, None, None).await?;
                 let response: ApplePodcastEntityResults =
                     serde_json::from_reader(api_resp.body.reader())?;
                 let feed_url = FeedUrl::new(Url::parse(
@@ -111,27 +116,22 @@ impl FeedUrl {
         &self,
         client: &HttpClient,
         etag: Option<String>,
+        lenient: bool,
     ) -> Result<Autodiscovered, AutodiscoveryException> {
         // if the initial request fails, there isn't much we can do
         let first = self.get(client, etag).await?;
+        let etag = first.etag.clone();
 
         // if we're able to parse a valid feed summary, return it
-        if let Ok(summary) = FeedSummary::new(first.url.to_string(), &first.body) {
-            return Ok(Autodiscovered {
-                summary,
-                etag: first.etag,
-            });
-        }
-
-        let mut reader = Cursor::new(first.body);
-        let urls = find_feed_links(&mut reader, first.url.as_str())
-            .pipe(prioritize_and_dedup_feed_urls)
-            .take(5);
+        let (first_error, urls) = match examine(first, lenient).await? {
+            Found::Feed(summary) => return Ok(Autodiscovered { summary, etag }),
+            Found::Page(err, urls) => (err, urls),
+        };
 
         let mut top: Option<FeedSummary> = None;
         for url in urls {
             tracing::debug!("Attempting to autodiscover from {}", url);
-            if let Some(candidate) = get_summary(client, url).await {
+            if let Some(candidate) = get_summary(client, url, lenient).await {
                 match &top {
                     Some(t) if candidate.len() > t.len() => {
                         top.replace(candidate);
@@ -144,20 +144,67 @@ impl FeedUrl {
             }
         }
 
-        top.map_or(Err(AutodiscoveryException::Failed), |summary| {
-            Ok(Autodiscovered {
+        match (top, first_error) {
+            (Some(summary), _) => Ok(Autodiscovered {
                 summary,
                 etag: None,
-            })
-        })
+            }),
+            // It looked like a feed, but it was broken and we couldn't find
+            // anything better, so the details are worth passing on.
+            (None, SummarizeError::Parse(err)) if err.is_in_feed() => {
+                Err(AutodiscoveryException::Parse(err))
+            }
+            (None, _) => Err(AutodiscoveryException::Failed),
+        }
     }
 }
 
-async fn get_summary(client: &HttpClient, url: FeedUrl) -> Option<FeedSummary> {
-    url.get(client, None)
-        .await
-        .ok()
-        .and_then(|fetched| FeedSummary::new(fetched.url.to_string(), &fetched.body).ok())
+enum Found {
+    Feed(FeedSummary),
+    /// Not a feed, though it may link to some.
+    Page(SummarizeError, Vec<FeedUrl>),
+}
+
+/// Summarizes what we fetched if it's a feed, or looks through it for links
+/// to the most likely feeds if it isn't.
+async fn examine(fetched: Spooled, lenient: bool) -> Result<Found, AutodiscoveryException> {
+    let Spooled {
+        file, url, refresh, ..
+    } = fetched;
+    let found = tokio::task::spawn_blocking(move || {
+        let mut page = file.try_clone()?;
+        let err = match summarize_spooled(url.to_string(), file, lenient) {
+            Ok((summary, _)) => return Ok(Found::Feed(summary)),
+            Err(SpooledError::Summarize(err)) => err,
+            Err(SpooledError::Io(err)) => return Err(err.into()),
+        };
+        page.seek(SeekFrom::Start(0))?;
+        let urls = find_feed_links(&mut BufReader::new(page), url.as_str())
+            .pipe(prioritize_and_dedup_feed_urls)
+            .take(5)
+            .collect();
+        Ok(Found::Page(err, urls))
+    })
+    .await?;
+    // Anyone who was waiting on this fetch can have it now.
+    if let Some(refresh) = refresh {
+        refresh.finish();
+    }
+    found
+}
+
+async fn get_summary(client: &HttpClient, url: FeedUrl, lenient: bool) -> Option<FeedSummary> {
+    let Spooled {
+        file, url, refresh, ..
+    } = url.get(client, None).await.ok()?;
+    let summary =
+        tokio::task::spawn_blocking(move || summarize_spooled(url.to_string(), file, lenient).ok())
+            .await
+            .ok()?;
+    if let Some(refresh) = refresh {
+        refresh.finish();
+    }
+    summary.map(|(summary, _)| summary)
 }
 
 fn find_feed_links<R: BufRead>(reader: &mut R, origin: &str) -> impl Iterator<Item = FeedUrl> {
@@ -0,0 +1,299 @@
+#![allow(clippy::result_large_err)]
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use headers::HeaderMap;
+use hyper::header;
+use tempfile::TempPath;
+use tokio::sync::OwnedMutexGuard;
+use url::Url;
+
+use crate::{
+    fetch::{FetchException, Spooled},
+    helpers::HeaderMapUtils,
+};
+
+/// Past this many, locks nobody is waiting on are forgotten.
+const MAX_LOCKS: usize = 1_000;
+
+/// How long a failed fetch is remembered for, however short `ttl` is, so
+/// everyone who was waiting on it hears how it went instead of each trying
+/// again in turn.
+const FAILURE_TTL: Duration = Duration::from_secs(5);
+
+/// Recently fetched feeds, kept around for a little while so everyone
+/// replaying the same feed at once shares a single fetch of it.
+#[derive(Clone, Debug)]
+pub(crate) struct FetchCache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    locks: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    cached: Cached,
+    expires: Instant,
+}
+
+#[derive(Debug)]
+enum Cached {
+    /// Still on disk, for as long as either the cache or anyone reading it
+    /// needs it.
+    Body {
+        path: Arc<TempPath>,
+        content_type: Option<String>,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        url: Url,
+    },
+    /// The origin told us the copy fetched with these hasn't changed.
+    NotModified {
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+    Failed(Arc<FetchException>),
+}
+
+/// Whoever holds this is fetching a feed from the origin, and anyone else
+/// after the same feed waits until it's dropped. The fetched copy is only
+/// shared with them once it's been dealt with (see `finish`), so they'll find
+/// it all done; otherwise they go on to fetch it themselves.
+#[derive(Debug)]
+pub struct Refresh {
+    cache: FetchCache,
+    uri: String,
+    fetched: Option<Entry>,
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl Refresh {
+    /// Keeps a freshly fetched body, which must already be written out to
+    /// `path` in full, for sharing once it's been dealt with.
+    pub(crate) fn keep_body(&mut self, headers: &HeaderMap, path: TempPath, spooled: &Spooled) {
+        self.fetched = Some(Entry {
+            cached: Cached::Body {
+                path: Arc::new(path),
+                content_type: spooled.content_type.clone(),
+                etag: spooled.etag.clone(),
+                last_modified: spooled.last_modified.clone(),
+                url: spooled.url.clone(),
+            },
+            expires: Instant::now() + cache_ttl(headers, self.cache.ttl),
+        });
+    }
+
+    /// Shares the fetched copy with everyone else after it, now that anything
+    /// new in it has been recorded.
+    pub fn finish(mut self) {
+        if let Some(entry) = self.fetched.take() {
+            self.cache.keep(&self.uri, entry);
+        }
+    }
+}
+
+impl FetchCache {
+    /// Nothing is kept if `ttl` is zero, though fetches are still shared.
+    pub fn new(ttl: Duration) -> Self {
+        FetchCache {
+            ttl,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            locks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Waits for any fetch of `uri` that's already under way.
+    pub async fn refresh(&self, uri: &str) -> Refresh {
+        let lock = {
+            let mut locks = self.locks.lock().expect("fetch cache poisoned");
+            if locks.len() >= MAX_LOCKS {
+                locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            }
+            locks.entry(uri.to_string()).or_default().clone()
+        };
+        Refresh {
+            cache: self.clone(),
+            uri: uri.to_string(),
+            fetched: None,
+            _guard: lock.lock_owned().await,
+        }
+    }
+
+    /// Answers as the origin would have, if it was asked recently enough.
+    pub fn get(
+        &self,
+        uri: &str,
+        etag: &Option<String>,
+        last_modified: &Option<String>,
+    ) -> Option<Result<Spooled, FetchException>> {
+        let entries = self.entries.lock().expect("fetch cache poisoned");
+        let entry = entries.get(uri).filter(|e| e.expires > Instant::now())?;
+        match &entry.cached {
+            Cached::Body {
+                path,
+                content_type,
+                etag: cached_etag,
+                last_modified: cached_last_modified,
+                url,
+            } => {
+                let unchanged = match (etag, cached_etag) {
+                    (Some(etag), Some(cached_etag)) => etag == cached_etag,
+                    (Some(_), None) => false,
+                    (None, _) => last_modified.is_some() && last_modified == cached_last_modified,
+                };
+                if unchanged {
+                    return Some(Err(FetchException::NotModified(etag.clone())));
+                }
+                Some(
+                    File::open(&**path)
+                        .map(|file| Spooled {
+                            file,
+                            content_type: content_type.clone(),
+                            etag: cached_etag.clone(),
+                            last_modified: cached_last_modified.clone(),
+                            url: url.clone(),
+                            moved: None,
+                            refresh: None,
+                        })
+                        .map_err(FetchException::from),
+                )
+            }
+            Cached::NotModified {
+                etag: cached_etag,
+                last_modified: cached_last_modified,
+            } => (etag == cached_etag && last_modified == cached_last_modified)
+                .then(|| Err(FetchException::NotModified(etag.clone()))),
+            Cached::Failed(err) => Some(Err(FetchException::Shared(err.clone()))),
+        }
+    }
+
+    pub fn insert_not_modified(
+        &self,
+        uri: &str,
+        headers: &HeaderMap,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    ) {
+        self.insert(
+            uri,
+            headers,
+            Cached::NotModified {
+                etag,
+                last_modified,
+            },
+        );
+    }
+
+    /// Remembers that fetching `uri` went wrong, giving back the error in a
+    /// form that can be handed to everyone else too.
+    pub fn insert_failure(&self, uri: &str, err: FetchException) -> FetchException {
+        let err = Arc::new(err);
+        self.keep(
+            uri,
+            Entry {
+                cached: Cached::Failed(err.clone()),
+                expires: Instant::now() + FAILURE_TTL,
+            },
+        );
+        FetchException::Shared(err)
+    }
+
+    fn insert(&self, uri: &str, headers: &HeaderMap, cached: Cached) {
+        self.keep(
+            uri,
+            Entry {
+                cached,
+                expires: Instant::now() + cache_ttl(headers, self.ttl),
+            },
+        );
+    }
+
+    fn keep(&self, uri: &str, entry: Entry) {
+        let mut entries = self.entries.lock().expect("fetch cache poisoned");
+        let now = Instant::now();
+        entries.retain(|_, entry| entry.expires > now);
+        if entry.expires > now {
+            entries.insert(uri.to_string(), entry);
+        } else {
+            entries.remove(uri);
+        }
+    }
+}
+
+/// How long a response can be reused for: never more than `max`, and less if
+/// the origin's `Cache-Control` says so.
+fn cache_ttl(headers: &HeaderMap, max: Duration) -> Duration {
+    let age = headers
+        .get_str(header::AGE)
+        .and_then(|age| age.trim().parse().ok())
+        .map_or(Duration::ZERO, Duration::from_secs);
+    let mut ttl = max;
+    for cache_control in headers.get_all(header::CACHE_CONTROL) {
+        for directive in cache_control.to_str().unwrap_or_default().split(',') {
+            let directive = directive.trim().to_ascii_lowercase();
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive.as_str(), None),
+            };
+            match name {
+                "no-store" | "no-cache" | "private" => return Duration::ZERO,
+                "max-age" | "s-maxage" => {
+                    let secs = value.and_then(|v| v.parse().ok()).unwrap_or(0);
+                    ttl = ttl.min(Duration::from_secs(secs));
+                }
+                _ => {}
+            }
+        }
+    }
+    ttl.saturating_sub(age)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use headers::{HeaderMap, HeaderValue};
+
+    use super::cache_ttl;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn respects_cache_control() {
+        let max = Duration::from_secs(60);
+        assert_eq!(cache_ttl(&headers(&[]), max), max);
+        assert_eq!(
+            cache_ttl(&headers(&[("cache-control", "public, max-age=3600")]), max),
+            max
+        );
+        assert_eq!(
+            cache_ttl(&headers(&[("cache-control", "max-age=30")]), max),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            cache_ttl(
+                &headers(&[("cache-control", "max-age=30"), ("age", "20")]),
+                max
+            ),
+            Duration::from_secs(10)
+        );
+        for value in ["no-store", "no-cache", "private, max-age=600", "max-age=0"] {
+            assert_eq!(
+                cache_ttl(&headers(&[("cache-control", value)]), max),
+                Duration::ZERO,
+                "{value}"
+            );
+        }
+    }
+}
@@ -14,6 +14,62 @@ pub struct Config {
     pub port: u16,
     pub user_agent: String,
     pub assets_path: String,
+    /// Where we're served from as the outside world sees it, like
+    /// `https://podreplay.com`, for feeds to link back to their replays.
+    /// Without it that's worked out from each request's `Host` and
+    /// `X-Forwarded-Proto` headers, which clients can set to anything.
+    pub public_base_url: Option<String>,
+    /// Seconds between checks of every known feed, or 0 to turn polling off.
+    pub poll_interval: u64,
+    /// How many feeds can be fetched at once while polling.
+    pub poll_concurrency: usize,
+    /// Up to this many seconds are added before each fetch, to avoid hitting
+    /// every origin at the same moment.
+    pub poll_jitter: u64,
+    /// Seconds a stored copy of a feed can be served for while the origin is
+    /// failing.
+    pub max_staleness: u64,
+    /// Networks (or single addresses) that can be fetched from even though
+    /// they'd otherwise be off limits, like `127.0.0.1` for local testing.
+    pub fetch_allow: Vec<String>,
+    /// Networks that can't be fetched from, on top of the private, loopback
+    /// and link-local ranges that never can be.
+    pub fetch_deny: Vec<String>,
+    /// The most we'll download of any one feed, in bytes.
+    pub fetch_max_body_size: u64,
+    /// Seconds to wait for a connection to an origin.
+    pub fetch_connect_timeout: u64,
+    /// Seconds to wait for an origin to start responding (each time it's
+    /// tried), and then between each part of the body.
+    pub fetch_read_timeout: u64,
+    /// The most seconds any one fetch can take altogether, retries and all,
+    /// however steadily the body keeps arriving.
+    pub fetch_total_timeout: u64,
+    /// How many more times to try a fetch that failed in a way that might not
+    /// last (the connection failing, a 429 or 5xx), or 0 to never retry.
+    pub fetch_retries: u32,
+    /// Milliseconds to wait before the first retry, doubling for each one
+    /// after that.
+    pub fetch_retry_delay: u64,
+    /// The most milliseconds to wait before any one retry. Origins that ask
+    /// (with `Retry-After`) for longer than this aren't retried at all.
+    pub fetch_retry_max_delay: u64,
+    /// How many requests each client can make, or 0 for no limit.
+    pub client_requests_per_minute: u32,
+    /// A header holding the client's address, as set by a proxy in front of
+    /// us (`Fly-Client-IP`, say). Only set this if the proxy can be trusted to
+    /// overwrite whatever the client sent.
+    pub client_ip_header: Option<String>,
+    /// How many feeds we'll fetch from any one host, or 0 for no limit.
+    pub origin_requests_per_minute: u32,
+    /// Seconds a fetched feed is reused for before going back to the origin,
+    /// or less if its `Cache-Control` says so. 0 turns this off.
+    pub fetch_cache_ttl: u64,
+    /// When a feed redirects for good to somewhere we've already been keeping
+    /// a history for, merge the two. Off by default, since any feed can
+    /// redirect anywhere and would then mix its items into the other's
+    /// replays. Moves declared with `itunes:new-feed-url` are never merged.
+    pub merge_moved_feeds: bool,
 }
 
 impl Default for Config {
@@ -25,6 +81,25 @@ impl Default for Config {
             port: 8080,
             user_agent: "podreplay.com".to_string(),
             assets_path: "ui".to_string(),
+            public_base_url: None,
+            poll_interval: 60 * 60,
+            poll_concurrency: 4,
+            poll_jitter: 5 * 60,
+            max_staleness: 7 * 24 * 60 * 60,
+            fetch_allow: Vec::new(),
+            fetch_deny: Vec::new(),
+            fetch_max_body_size: 64 * 1024 * 1024,
+            fetch_connect_timeout: 10,
+            fetch_read_timeout: 30,
+            fetch_total_timeout: 5 * 60,
+            fetch_retries: 2,
+            fetch_retry_delay: 500,
+            fetch_retry_max_delay: 5_000,
+            client_requests_per_minute: 60,
+            client_ip_header: None,
+            origin_requests_per_minute: 120,
+            fetch_cache_ttl: 60,
+            merge_moved_feeds: false,
         }
     }
 }
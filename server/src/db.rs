@@ -9,6 +9,8 @@ use sqlx::{
 };
 use tracing::log::LevelFilter;
 
+use crate::{replay::StoredFeed, replays::StoredReplay};
+
 #[derive(Clone)]
 pub struct Db {
     uri: String,
@@ -59,21 +61,24 @@ impl Db {
         uri: &str,
         timestamp: &DateTime<Utc>,
         etag: &Option<String>,
+        last_modified: &Option<String>,
     ) -> Result<FeedMeta, sqlx::Error> {
         let id = sqlx::query_scalar!(
             r#"
-            INSERT INTO feeds (uri, first_fetched, last_fetched, etag)
-            VALUES (?, ?, ?, ?)
+            INSERT INTO feeds (uri, first_fetched, last_fetched, etag, last_modified)
+            VALUES (?, ?, ?, ?, ?)
             ON CONFLICT(uri)
             DO UPDATE SET
                 last_fetched=excluded.last_fetched,
-                etag=excluded.etag
+                etag=excluded.etag,
+                last_modified=excluded.last_modified
             RETURNING id
             ;"#,
             uri,
             timestamp,
             timestamp,
-            etag
+            etag,
+            last_modified
         )
         .fetch_one(&self.pool)
         .await?;
@@ -82,6 +87,198 @@ impl Db {
             .await
     }
 
+    #[tracing::instrument(level = "debug")]
+    pub async fn get_feed(&self, uri: &str) -> Result<Option<FeedMeta>, sqlx::Error> {
+        sqlx::query_as!(FeedMeta, "SELECT * FROM feeds WHERE uri = ?", uri)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    /// Where the feed once known as `uri` lives now, or just `uri` if it
+    /// hasn't moved.
+    #[tracing::instrument(level = "debug")]
+    pub async fn resolve_feed_uri(&self, uri: &str) -> Result<String, sqlx::Error> {
+        let moved = sqlx::query_scalar!(
+            r#"
+            SELECT feeds.uri
+            FROM feed_aliases
+            JOIN feeds ON feeds.id = feed_aliases.feed_id
+            WHERE feed_aliases.uri = ?
+            ;"#,
+            uri
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(moved.unwrap_or_else(|| uri.to_string()))
+    }
+
+    /// Moves the feed at `from` over to `to`, keeping `from` as an alias so
+    /// its history carries on. If `to` already has a history of its own, the
+    /// two are only merged if `merge` is set (keeping whichever noticed each
+    /// entry first), and otherwise nothing changes. Returns whether it moved.
+    #[tracing::instrument(level = "debug")]
+    pub async fn move_feed(
+        &self,
+        from: &str,
+        to: &str,
+        timestamp: &DateTime<Utc>,
+        merge: bool,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let Some(from_id) = sqlx::query_scalar!("SELECT id FROM feeds WHERE uri = ?", from)
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            return Ok(false);
+        };
+        let to_id = match sqlx::query_scalar!("SELECT id FROM feeds WHERE uri = ?", to)
+            .fetch_optional(&mut *tx)
+            .await?
+        {
+            Some(id) => Some(id),
+            None => {
+                sqlx::query_scalar!("SELECT feed_id FROM feed_aliases WHERE uri = ?", to)
+                    .fetch_optional(&mut *tx)
+                    .await?
+            }
+        };
+
+        let feed_id = match to_id {
+            Some(to_id) if to_id != from_id => {
+                if !merge {
+                    return Ok(false);
+                }
+                sqlx::query!(
+                    r#"
+                    DELETE FROM entries
+                    WHERE feed_id = ? AND EXISTS (
+                        SELECT 1 FROM entries AS earlier
+                        WHERE earlier.feed_id = ?
+                        AND earlier.id = entries.id
+                        AND earlier.noticed <= entries.noticed
+                    )
+                    ;"#,
+                    to_id,
+                    from_id
+                )
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query!(
+                    r#"
+                    DELETE FROM entries
+                    WHERE feed_id = ? AND id IN (SELECT id FROM entries WHERE feed_id = ?)
+                    ;"#,
+                    from_id,
+                    to_id
+                )
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query!(
+                    "UPDATE entries SET feed_id = ? WHERE feed_id = ?",
+                    to_id,
+                    from_id
+                )
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query!(
+                    "UPDATE feed_aliases SET feed_id = ? WHERE feed_id = ?",
+                    to_id,
+                    from_id
+                )
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query!("DELETE FROM feeds WHERE id = ?", from_id)
+                    .execute(&mut *tx)
+                    .await?;
+                to_id
+            }
+            // Either somewhere new, or back to where this same feed used to be.
+            _ => {
+                sqlx::query!("DELETE FROM feed_aliases WHERE uri = ?", to)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query!("UPDATE feeds SET uri = ? WHERE id = ?", to, from_id)
+                    .execute(&mut *tx)
+                    .await?;
+                from_id
+            }
+        };
+        sqlx::query!(
+            r#"
+            INSERT INTO feed_aliases (uri, feed_id, added)
+            VALUES (?, ?, ?)
+            ON CONFLICT(uri)
+            DO UPDATE SET feed_id=excluded.feed_id, added=excluded.added
+            ;"#,
+            from,
+            feed_id,
+            timestamp
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Whether the feed at `from` already said it moved to `to` back when it
+    /// had this etag, and `to` didn't check out.
+    #[tracing::instrument(level = "debug")]
+    pub async fn is_unconfirmed_move(
+        &self,
+        from: &str,
+        to: &str,
+        etag: &Option<String>,
+    ) -> Result<bool, sqlx::Error> {
+        let found = sqlx::query!(
+            r#"
+            SELECT feed_id
+            FROM unconfirmed_moves
+            WHERE feed_id = (SELECT id FROM feeds WHERE uri = ?)
+            AND uri = ? AND etag IS ?
+            ;"#,
+            from,
+            to,
+            etag
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(found.is_some())
+    }
+
+    /// Remembers that `to` didn't check out when the feed at `from` (with
+    /// this etag) said it moved there, replacing whatever it said before.
+    #[tracing::instrument(level = "debug")]
+    pub async fn record_unconfirmed_move(
+        &self,
+        from: &str,
+        to: &str,
+        etag: &Option<String>,
+        checked: &DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO unconfirmed_moves (feed_id, uri, etag, checked)
+            SELECT id, ?, ?, ? FROM feeds WHERE uri = ?
+            ON CONFLICT(feed_id)
+            DO UPDATE SET uri=excluded.uri, etag=excluded.etag, checked=excluded.checked
+            ;"#,
+            to,
+            etag,
+            checked,
+            from
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug")]
+    pub async fn get_feeds(&self) -> Result<Vec<FeedMeta>, sqlx::Error> {
+        sqlx::query_as!(FeedMeta, "SELECT * FROM feeds ORDER BY id")
+            .fetch_all(&self.pool)
+            .await
+    }
+
     #[tracing::instrument(level = "debug")]
     pub async fn get_entries(&self, feed_id: i64) -> Result<Vec<CachedEntry>, sqlx::Error> {
         sqlx::query_as!(
@@ -119,6 +316,166 @@ impl Db {
         }
         self.get_entries(feed_id).await
     }
+
+    #[tracing::instrument(level = "debug", skip(feed), fields(feed_id = feed.feed_id))]
+    pub async fn save_feed_body(&self, feed: &StoredFeed) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO feed_bodies (feed_id, fetched, etag, last_modified, content_type, body)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT(feed_id)
+            DO UPDATE SET
+                fetched=excluded.fetched,
+                etag=excluded.etag,
+                last_modified=excluded.last_modified,
+                content_type=excluded.content_type,
+                body=excluded.body
+            ;"#,
+            feed.feed_id,
+            feed.fetched,
+            feed.etag,
+            feed.last_modified,
+            feed.content_type,
+            feed.body
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug")]
+    pub async fn get_feed_body(&self, uri: &str) -> Result<Option<StoredFeed>, sqlx::Error> {
+        sqlx::query_as!(
+            StoredFeed,
+            "SELECT * FROM feed_bodies WHERE feed_id = (SELECT id FROM feeds WHERE uri = ?)",
+            uri
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Whether the stored body has this etag and length, in which case it's
+    /// almost certainly the same one and there's no need to write it again.
+    #[tracing::instrument(level = "debug")]
+    pub async fn has_feed_body(
+        &self,
+        uri: &str,
+        etag: &str,
+        len: i64,
+    ) -> Result<bool, sqlx::Error> {
+        let found = sqlx::query!(
+            r#"
+            SELECT feed_id
+            FROM feed_bodies
+            WHERE feed_id = (SELECT id FROM feeds WHERE uri = ?)
+            AND etag = ? AND length(body) = ?
+            ;"#,
+            uri,
+            etag,
+            len
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(found.is_some())
+    }
+
+    /// Marks the stored body as fetched just now, after the origin said it
+    /// hasn't changed since it was fetched with `etag` (if it has one).
+    #[tracing::instrument(level = "debug")]
+    pub async fn touch_feed_body(
+        &self,
+        uri: &str,
+        fetched: &DateTime<Utc>,
+        etag: &Option<String>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE feed_bodies
+            SET fetched = ?
+            WHERE feed_id = (SELECT id FROM feeds WHERE uri = ?)
+            AND (? IS NULL OR etag = ?)
+            ;"#,
+            fetched,
+            uri,
+            etag,
+            etag
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The etag and last modified date the stored body was fetched with,
+    /// without loading the body.
+    #[tracing::instrument(level = "debug")]
+    pub async fn get_feed_body_validators(
+        &self,
+        uri: &str,
+    ) -> Result<(Option<String>, Option<String>), sqlx::Error> {
+        let validators = sqlx::query!(
+            r#"
+            SELECT etag, last_modified
+            FROM feed_bodies
+            WHERE feed_id = (SELECT id FROM feeds WHERE uri = ?)
+            ;"#,
+            uri
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(validators.map_or((None, None), |row| (row.etag, row.last_modified)))
+    }
+
+    #[tracing::instrument(level = "debug")]
+    pub async fn create_replay(&self, replay: &StoredReplay) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO replays (slug, edit_token_hash, uri, rule, start, first, last, title, created, updated)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ;"#,
+            replay.slug,
+            replay.edit_token_hash,
+            replay.uri,
+            replay.rule,
+            replay.start,
+            replay.first,
+            replay.last,
+            replay.title,
+            replay.created,
+            replay.updated
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug")]
+    pub async fn get_replay(&self, slug: &str) -> Result<Option<StoredReplay>, sqlx::Error> {
+        sqlx::query_as!(StoredReplay, "SELECT * FROM replays WHERE slug = ?", slug)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    #[tracing::instrument(level = "debug")]
+    pub async fn update_replay(&self, replay: &StoredReplay) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE replays
+            SET uri = ?, rule = ?, start = ?, first = ?, last = ?, title = ?, updated = ?
+            WHERE slug = ?
+            ;"#,
+            replay.uri,
+            replay.rule,
+            replay.start,
+            replay.first,
+            replay.last,
+            replay.title,
+            replay.updated,
+            replay.slug
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -126,4 +483,6 @@ impl Db {
 fn schemas() {
     let _ = sqlx::query_as!(FeedMeta, "SELECT * FROM feeds;");
     let _ = sqlx::query_as!(CachedEntry, "SELECT * FROM entries;");
+    let _ = sqlx::query_as!(StoredReplay, "SELECT * FROM replays;");
+    let _ = sqlx::query_as!(StoredFeed, "SELECT * FROM feed_bodies;");
 }
@@ -1,20 +1,93 @@
-#![allow(clippy::large_enum_variant)]
+#![allow(clippy::large_enum_variant, clippy::result_large_err)]
 
-use std::time::Duration;
+use std::{
+    cell::Cell,
+    fs::File,
+    future::Future,
+    io::{Seek, SeekFrom},
+    sync::Arc,
+    time::Duration,
+};
 
-use axum::body::Bytes;
+use axum::{
+    body::{BoxBody, Bytes},
+    response::{IntoResponse, Response},
+};
 use hyper::{header, StatusCode};
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_tracing::TracingMiddleware;
 use thiserror::Error;
+use tokio::io::AsyncWriteExt;
 use url::Url;
 
-use crate::helpers::HeaderMapUtils;
+use crate::{
+    cache::{FetchCache, Refresh},
+    config::Config,
+    guard::{AddressGuard, Blocked, GuardedResolver},
+    helpers::HeaderMapUtils,
+    limit::{too_many_requests, RateLimiter},
+    problem::Problem,
+    retry::{Retry, TimedOut},
+};
+
+/// The same limit reqwest uses by default.
+const MAX_REDIRECTS: usize = 10;
+
+/// The share of each origin's requests that background fetches leave alone.
+const INTERACTIVE_RESERVE: f64 = 0.5;
+
+tokio::task_local! {
+    /// Whether every redirect followed so far for the request being sent was
+    /// a permanent one.
+    static ALL_PERMANENT: Cell<bool>;
+}
 
 #[derive(Clone)]
 pub struct HttpClient {
     user_agent: String,
     client: ClientWithMiddleware,
+    guard: AddressGuard,
+    limits: FetchLimits,
+    origins: Arc<RateLimiter<String>>,
+    cache: FetchCache,
+}
+
+/// Keeps one broken or hostile origin from tying up memory or connections.
+#[derive(Clone, Copy, Debug)]
+pub struct FetchLimits {
+    /// In bytes, counted as the body arrives.
+    pub max_body_size: u64,
+    pub connect_timeout: Duration,
+    /// How long to wait for the response to start (each time it's tried),
+    /// and then for each chunk.
+    pub read_timeout: Duration,
+    /// For the whole fetch, so an origin can't hold one open forever by
+    /// sending a little at a time.
+    pub total_timeout: Duration,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub retry_max_delay: Duration,
+    /// Per host, so we can't be used to hammer anyone.
+    pub origin_requests_per_minute: u32,
+    /// The longest a fetched feed is reused for, so popular feeds don't get
+    /// fetched again for every replay of them.
+    pub cache_ttl: Duration,
+}
+
+impl From<&Config> for FetchLimits {
+    fn from(config: &Config) -> Self {
+        FetchLimits {
+            max_body_size: config.fetch_max_body_size,
+            connect_timeout: Duration::from_secs(config.fetch_connect_timeout),
+            read_timeout: Duration::from_secs(config.fetch_read_timeout),
+            total_timeout: Duration::from_secs(config.fetch_total_timeout),
+            retries: config.fetch_retries,
+            retry_delay: Duration::from_millis(config.fetch_retry_delay),
+            retry_max_delay: Duration::from_millis(config.fetch_retry_max_delay),
+            origin_requests_per_minute: config.origin_requests_per_minute,
+            cache_ttl: Duration::from_secs(config.fetch_cache_ttl),
+        }
+    }
 }
 
 impl std::fmt::Debug for HttpClient {
@@ -27,13 +100,31 @@ pub struct Fetched {
     pub body: Bytes,
     pub content_type: Option<String>,
     pub etag: Option<String>,
+    pub last_modified: Option<String>,
     pub url: Url,
 }
 
+/// A fetched body written out to a temporary file as it arrived, so large
+/// feeds don't need to be held in memory.
+pub struct Spooled {
+    pub file: File,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub url: Url,
+    /// Where the feed was permanently redirected to, if it was. Only known
+    /// for feeds just fetched from the origin.
+    pub moved: Option<Url>,
+    /// Only set if this was just fetched from the origin, rather than shared
+    /// from someone else's fetch of it. Nobody else gets this copy until it's
+    /// finished, see `Refresh`.
+    pub refresh: Option<Refresh>,
+}
+
 #[derive(Error, Debug)]
 pub enum FetchException {
     #[error("{0}")]
-    Request(#[from] reqwest_middleware::Error),
+    Request(reqwest_middleware::Error),
     #[error("Failed to fetch feed")]
     Response(reqwest::Response),
     #[error("{0}")]
@@ -42,39 +133,316 @@ pub enum FetchException {
     JsonParse(#[from] serde_json::Error),
     #[error("{0}")]
     UrlParse(#[from] url::ParseError),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Blocked(#[from] Blocked),
+    #[error("Feed is larger than {0} bytes")]
+    TooLarge(u64),
+    #[error("Timed out fetching feed")]
+    Timeout,
+    #[error("Too many requests to {host}, please try again later")]
+    RateLimited { host: String, retry_after: Duration },
     #[error("Unknown")]
     Unknown,
     #[error("Unknown")]
     NotModified(Option<String>),
+    /// How someone else's fetch of the same feed went.
+    #[error("{0}")]
+    Shared(Arc<FetchException>),
+}
+
+impl FetchException {
+    /// Whether the origin can't be reached right now, either because it (or
+    /// the way there) seems to be having trouble or because we're holding
+    /// back, as opposed to it turning down this particular request.
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            Self::Request(_) | Self::Read(_) | Self::Timeout | Self::RateLimited { .. } => true,
+            Self::Response(resp) => resp.status().is_server_error(),
+            Self::Shared(err) => err.is_unavailable(),
+            _ => false,
+        }
+    }
+}
+
+/// Refusing to connect is kept apart from failing to, so it isn't taken for
+/// the origin being down.
+impl From<reqwest_middleware::Error> for FetchException {
+    fn from(err: reqwest_middleware::Error) -> Self {
+        match Blocked::find(&err) {
+            Some(blocked) => Self::Blocked(blocked.clone()),
+            None => Self::Request(err),
+        }
+    }
+}
+
+impl IntoResponse for FetchException {
+    fn into_response(self) -> Response<BoxBody> {
+        self.response()
+    }
+}
+
+impl FetchException {
+    fn response(&self) -> Response<BoxBody> {
+        match self {
+            Self::TooLarge(_) => {
+                Problem::new(StatusCode::BAD_GATEWAY, self.to_string()).into_response()
+            }
+            Self::Timeout => {
+                Problem::new(StatusCode::GATEWAY_TIMEOUT, self.to_string()).into_response()
+            }
+            Self::RateLimited { retry_after, .. } => {
+                too_many_requests(self.to_string(), *retry_after)
+            }
+            Self::Shared(err) => err.response(),
+            _ => StatusCode::BAD_GATEWAY.into_response(),
+        }
+    }
 }
 
 impl HttpClient {
-    pub fn new(user_agent: String) -> Self {
+    pub fn new(user_agent: String, guard: AddressGuard, limits: FetchLimits) -> Self {
+        let redirect_guard = guard.clone();
+        let redirect = reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if let Err(err) = redirect_guard.check_url(attempt.url()) {
+                attempt.error(err)
+            } else {
+                let permanent = matches!(
+                    attempt.status(),
+                    StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT
+                );
+                // Not set while sending requests that don't care.
+                let _ = ALL_PERMANENT.try_with(|all| all.set(all.get() && permanent));
+                attempt.follow()
+            }
+        });
         let client = reqwest::ClientBuilder::new()
-            .timeout(Duration::from_secs(30))
+            .connect_timeout(limits.connect_timeout)
+            .dns_resolver(Arc::new(GuardedResolver(guard.clone())))
+            .redirect(redirect)
             .build()
             .expect("Failed to construct http client");
+        let origins = Arc::new(RateLimiter::new(limits.origin_requests_per_minute));
         let client = ClientBuilder::new(client)
             .with(TracingMiddleware::default())
+            .with(Retry::new(&limits, origins.clone()))
             .build();
-        HttpClient { client, user_agent }
+        HttpClient {
+            client,
+            user_agent,
+            guard,
+            limits,
+            origins,
+            cache: FetchCache::new(limits.cache_ttl),
+        }
+    }
+
+    #[tracing::instrument(level = "debug")]
+    pub async fn get(
+        &self,
+        uri: &str,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    ) -> Result<Fetched, FetchException> {
+        self.within_deadline(async {
+            let mut resp = self.send(uri, etag, last_modified).await?;
+
+            let url = resp.url().clone();
+            let headers = resp.headers();
+            let etag = headers.get_string(header::ETAG);
+            let last_modified = headers.get_string(header::LAST_MODIFIED);
+            let content_type = headers.get_string(header::CONTENT_TYPE);
+
+            let mut body = Vec::new();
+            let mut len = 0;
+            while let Some(chunk) = self.next_chunk(&mut resp, &mut len).await? {
+                body.extend_from_slice(&chunk);
+            }
+            tracing::trace!(?etag, ?body);
+
+            Ok(Fetched {
+                body: body.into(),
+                etag,
+                last_modified,
+                content_type,
+                url,
+            })
+        })
+        .await
     }
 
+    /// Like `get`, but writes the body to a temporary file chunk by chunk
+    /// instead of collecting it in memory. Feeds fetched recently are shared
+    /// rather than fetched again, and so is how fetches already under way
+    /// turn out, failures included.
     #[tracing::instrument(level = "debug")]
-    pub async fn get(&self, uri: &str, etag: Option<String>) -> Result<Fetched, FetchException> {
+    pub async fn get_spooled(
+        &self,
+        uri: &str,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    ) -> Result<Spooled, FetchException> {
+        let mut refresh = self.cache.refresh(uri).await;
+        if let Some(cached) = self.cache.get(uri, &etag, &last_modified) {
+            tracing::debug!("Reusing recent fetch");
+            return cached;
+        }
+
+        let fetched = self
+            .within_deadline(async {
+                let (resp, all_permanent) = ALL_PERMANENT
+                    .scope(Cell::new(true), async {
+                        let resp = self.request(uri, &etag, &last_modified).await;
+                        (resp, ALL_PERMANENT.with(Cell::get))
+                    })
+                    .await;
+                let resp = resp?;
+                let moved = Some(resp.url().clone())
+                    .filter(|url| all_permanent && Url::parse(uri).ok().as_ref() != Some(url));
+                let headers = resp.headers().clone();
+                let mut resp = match self.check_response(resp, etag.clone()) {
+                    Err(FetchException::NotModified(not_modified_etag)) => {
+                        self.cache
+                            .insert_not_modified(uri, &headers, etag, last_modified);
+                        return Err(FetchException::NotModified(not_modified_etag));
+                    }
+                    resp => resp?,
+                };
+
+                let url = resp.url().clone();
+                let etag = headers.get_string(header::ETAG);
+                let last_modified = headers.get_string(header::LAST_MODIFIED);
+                let content_type = headers.get_string(header::CONTENT_TYPE);
+
+                let (file, path) = tempfile::NamedTempFile::new()?.into_parts();
+                let mut file = tokio::fs::File::from_std(file);
+                let mut len = 0;
+                while let Some(chunk) = self.next_chunk(&mut resp, &mut len).await? {
+                    file.write_all(&chunk).await?;
+                }
+                file.flush().await?;
+                tracing::trace!(?etag, len);
+
+                let mut file = file.into_std().await;
+                file.seek(SeekFrom::Start(0))?;
+                let spooled = Spooled {
+                    file,
+                    etag,
+                    last_modified,
+                    content_type,
+                    url,
+                    moved,
+                    refresh: None,
+                };
+                Ok((spooled, headers, path))
+            })
+            .await;
+        // Still holding on to `refresh`, so whoever's waiting finds out how
+        // this went rather than trying again themselves.
+        match fetched {
+            Ok((mut spooled, headers, path)) => {
+                refresh.keep_body(&headers, path, &spooled);
+                spooled.refresh = Some(refresh);
+                Ok(spooled)
+            }
+            Err(err @ FetchException::NotModified(_)) => Err(err),
+            Err(err) => Err(self.cache.insert_failure(uri, err)),
+        }
+    }
+
+    /// Waits until `uri`'s origin has requests to spare beyond the share kept
+    /// for replays people are waiting on, for background fetches that would
+    /// rather take their time than be turned away (or crowd anyone else out).
+    pub async fn wait_for_origin(&self, uri: &str) {
+        let Some(host) = Url::parse(uri)
+            .ok()
+            .and_then(|url| url.host_str().map(String::from))
+        else {
+            return;
+        };
+        loop {
+            let wait = self.origins.spare_after(&host, INTERACTIVE_RESERVE);
+            if wait.is_zero() {
+                return;
+            }
+            tracing::debug!(?wait, "Waiting for {} to have requests to spare", host);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Gives up on `fetch` once it's taken longer than the total timeout.
+    async fn within_deadline<T>(
+        &self,
+        fetch: impl Future<Output = Result<T, FetchException>>,
+    ) -> Result<T, FetchException> {
+        tokio::time::timeout(self.limits.total_timeout, fetch)
+            .await
+            .unwrap_or(Err(FetchException::Timeout))
+    }
+
+    async fn send(
+        &self,
+        uri: &str,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    ) -> Result<reqwest::Response, FetchException> {
+        let resp = self.request(uri, &etag, &last_modified).await?;
+        self.check_response(resp, etag)
+    }
+
+    async fn request(
+        &self,
+        uri: &str,
+        etag: &Option<String>,
+        last_modified: &Option<String>,
+    ) -> Result<reqwest::Response, FetchException> {
+        let url = Url::parse(uri)?;
+        self.guard.check_url(&url)?;
+        if let Some(host) = url.host_str() {
+            self.origins
+                .check(host.to_string())
+                .map_err(|retry_after| FetchException::RateLimited {
+                    host: host.to_string(),
+                    retry_after,
+                })?;
+        }
         let req = self
             .client
             .get(uri)
             .header(header::USER_AGENT, &self.user_agent);
-        let req = if let Some(etag) = &etag {
+        let req = if let Some(etag) = etag {
             req.header(header::IF_NONE_MATCH, etag)
         } else {
             req
         };
-        let resp = req.send().await?;
+        let req = if let Some(last_modified) = last_modified {
+            req.header(header::IF_MODIFIED_SINCE, last_modified)
+        } else {
+            req
+        };
+        // Each attempt has its own timeout, see `Retry`.
+        let resp = match req.send().await {
+            Err(reqwest_middleware::Error::Reqwest(err)) if err.is_timeout() => {
+                return Err(FetchException::Timeout)
+            }
+            Err(reqwest_middleware::Error::Middleware(err)) if err.is::<TimedOut>() => {
+                return Err(FetchException::Timeout)
+            }
+            resp => resp?,
+        };
 
         tracing::trace!("status {:?}", resp.status());
+        Ok(resp)
+    }
 
+    fn check_response(
+        &self,
+        resp: reqwest::Response,
+        etag: Option<String>,
+    ) -> Result<reqwest::Response, FetchException> {
         if resp.status() == StatusCode::NOT_MODIFIED {
             return Err(FetchException::NotModified(
                 etag.or_else(|| resp.headers().get_string(header::ETAG)),
@@ -83,20 +451,34 @@ impl HttpClient {
         if !resp.status().is_success() {
             return Err(FetchException::Response(resp));
         }
+        // No sense starting on something we already know is too big.
+        if resp
+            .content_length()
+            .map_or(false, |len| len > self.limits.max_body_size)
+        {
+            return Err(FetchException::TooLarge(self.limits.max_body_size));
+        }
+        Ok(resp)
+    }
 
-        let url = resp.url().clone();
-        let headers = resp.headers();
-        let etag = headers.get_string(header::ETAG);
-        let content_type = headers.get_string(header::CONTENT_TYPE);
-
-        let body = resp.bytes().await?;
-        tracing::trace!(?etag, ?body);
-
-        Ok(Fetched {
-            body,
-            etag,
-            content_type,
-            url,
-        })
+    /// The next chunk of the body, as long as it arrives in time and doesn't
+    /// take the total past the limit.
+    async fn next_chunk(
+        &self,
+        resp: &mut reqwest::Response,
+        len: &mut u64,
+    ) -> Result<Option<Bytes>, FetchException> {
+        let chunk = match tokio::time::timeout(self.limits.read_timeout, resp.chunk()).await {
+            Ok(Err(err)) if err.is_timeout() => return Err(FetchException::Timeout),
+            Ok(chunk) => chunk?,
+            Err(_) => return Err(FetchException::Timeout),
+        };
+        if let Some(chunk) = &chunk {
+            *len += chunk.len() as u64;
+            if *len > self.limits.max_body_size {
+                return Err(FetchException::TooLarge(self.limits.max_body_size));
+            }
+        }
+        Ok(chunk)
     }
 }
@@ -0,0 +1,218 @@
+use std::{
+    error::Error,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use hyper::client::connect::dns::Name;
+use ipnet::IpNet;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use thiserror::Error;
+use url::{Host, Url};
+
+use crate::config::Config;
+
+/// Never fetched from unless explicitly allowed: loopback, private and
+/// link-local (cloud metadata services live there) ranges, along with anything
+/// else that isn't a public unicast address. That includes the ways of
+/// reaching IPv4 addresses over IPv6 (NAT64, 6to4 and the old IPv4-compatible
+/// addresses), which could otherwise lead anywhere.
+const RESTRICTED: &[&str] = &[
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/96",
+    "64:ff9b::/96",
+    "2001::/32",
+    "2002::/16",
+    "fc00::/7",
+    "fe80::/10",
+    "ff00::/8",
+];
+
+#[derive(Error, Clone, Debug)]
+#[error("Refusing to fetch from {0}")]
+pub struct Blocked(pub String);
+
+impl Blocked {
+    /// Finds out whether `err` happened because we refused to connect, however
+    /// deep inside other errors that ended up.
+    pub fn find<'a>(err: &'a (dyn Error + 'static)) -> Option<&'a Blocked> {
+        let mut source = Some(err);
+        while let Some(err) = source {
+            if let Some(blocked) = err.downcast_ref() {
+                return Some(blocked);
+            }
+            source = err.source();
+        }
+        None
+    }
+}
+
+/// Decides which addresses we're willing to fetch feeds from, so a request
+/// can't be used to reach anything on our side of the network.
+#[derive(Clone, Debug)]
+pub struct AddressGuard {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl AddressGuard {
+    /// Entries are networks (`10.1.0.0/16`) or single addresses. `allow` wins
+    /// over everything else, and `deny` adds to the restricted ranges.
+    pub fn new(allow: &[String], deny: &[String]) -> Result<Self, ipnet::AddrParseError> {
+        let restricted = RESTRICTED
+            .iter()
+            .map(|net| net.parse().expect("invalid restricted range"));
+        let deny = deny
+            .iter()
+            .map(|net| parse_net(net))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(AddressGuard {
+            allow: allow
+                .iter()
+                .map(|net| parse_net(net))
+                .collect::<Result<_, _>>()?,
+            deny: restricted.chain(deny).collect(),
+        })
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self, ipnet::AddrParseError> {
+        AddressGuard::new(&config.fetch_allow, &config.fetch_deny)
+    }
+
+    /// IPv6 addresses with an IPv4 address inside have to pass for both, or
+    /// ::ffff:127.0.0.1 (or 64:ff9b::7f00:1, if NAT64 is allowed) would get
+    /// through.
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let embedded = match ip {
+            IpAddr::V6(v6) => embedded_ipv4(v6).map(IpAddr::V4),
+            IpAddr::V4(_) => None,
+        };
+        self.allows_one(ip) && embedded.map_or(true, |ip| self.allows_one(ip))
+    }
+
+    fn allows_one(&self, ip: IpAddr) -> bool {
+        self.allow.iter().any(|net| net.contains(&ip))
+            || !self.deny.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Hosts given as addresses never reach the resolver, so they're checked
+    /// here instead (including on each redirect).
+    pub fn check_url(&self, url: &Url) -> Result<(), Blocked> {
+        let ip = match url.host() {
+            Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+            Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+            _ => return Ok(()),
+        };
+        if self.allows(ip) {
+            Ok(())
+        } else {
+            Err(Blocked(ip.to_string()))
+        }
+    }
+}
+
+/// The IPv4 address an IPv6 one stands in for: IPv4-mapped and -compatible
+/// (`::ffff:a.b.c.d`, `::a.b.c.d`), NAT64 (`64:ff9b::a.b.c.d`), 6to4
+/// (`2002:aabb:ccdd::`) and Teredo (`2001:0:…`, ending in the client's
+/// address with every bit flipped).
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let octets = ip.octets();
+    let last = || Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]);
+    match ip.segments() {
+        // Unspecified and loopback, which are checked as they are.
+        [0, 0, 0, 0, 0, 0, 0, 0 | 1] => None,
+        [0, 0, 0, 0, 0, 0 | 0xffff, _, _] => Some(last()),
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => Some(last()),
+        [0x2002, ..] => Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5])),
+        [0x2001, 0, ..] => Some(Ipv4Addr::from(!u32::from(last()))),
+        _ => None,
+    }
+}
+
+fn parse_net(net: &str) -> Result<IpNet, ipnet::AddrParseError> {
+    net.parse()
+        .or_else(|err| net.parse::<IpAddr>().map(IpNet::from).map_err(|_| err))
+}
+
+/// Resolves names as usual, leaving out any addresses the guard won't allow.
+pub(crate) struct GuardedResolver(pub AddressGuard);
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let guard = self.0.clone();
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0)).await?;
+            let allowed: Vec<SocketAddr> = addrs.filter(|addr| guard.allows(addr.ip())).collect();
+            if allowed.is_empty() {
+                return Err(Blocked(name.as_str().to_string()).into());
+            }
+            Ok(Box::new(allowed.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AddressGuard;
+
+    #[test]
+    fn restricts_internal_addresses() {
+        let guard = AddressGuard::new(&[], &[]).unwrap();
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.20.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "::1",
+            "::ffff:127.0.0.1",
+            "::127.0.0.1",
+            "fd00::1",
+            "fe80::1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:c0a8:101::1",
+            "2001:0:4136:e378:8000:63bf:80ff:fffe",
+            "198.18.0.1",
+            "192.0.0.8",
+        ] {
+            assert!(!guard.allows(ip.parse().unwrap()), "{ip} was allowed");
+        }
+        for ip in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(guard.allows(ip.parse().unwrap()), "{ip} wasn't allowed");
+        }
+    }
+
+    #[test]
+    fn applies_allow_and_deny_lists() {
+        let guard = AddressGuard::new(&["127.0.0.1".to_string()], &["93.184.216.0/24".to_string()])
+            .unwrap();
+        assert!(guard.allows("127.0.0.1".parse().unwrap()));
+        assert!(!guard.allows("127.0.0.2".parse().unwrap()));
+        assert!(!guard.allows("93.184.216.34".parse().unwrap()));
+        assert!(AddressGuard::new(&["nope".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn checks_ipv4_addresses_inside_ipv6_ones() {
+        let guard = AddressGuard::new(&["64:ff9b::/96".to_string()], &[]).unwrap();
+        assert!(guard.allows("64:ff9b::5db8:d822".parse().unwrap()));
+        assert!(!guard.allows("64:ff9b::7f00:1".parse().unwrap()));
+        let guard = AddressGuard::new(&["127.0.0.1".to_string()], &[]).unwrap();
+        assert!(guard.allows("::ffff:127.0.0.1".parse().unwrap()));
+        assert!(!guard.allows("64:ff9b::7f00:1".parse().unwrap()));
+        // Teredo, for 93.184.216.34 and then 127.0.0.1.
+        let guard = AddressGuard::new(&["2001::/32".to_string()], &[]).unwrap();
+        assert!(guard.allows("2001:0:4136:e378:8000:63bf:a247:27dd".parse().unwrap()));
+        assert!(!guard.allows("2001:0:4136:e378:8000:63bf:80ff:fffe".parse().unwrap()));
+    }
+}
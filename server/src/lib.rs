@@ -1,8 +1,16 @@
 mod autodiscovery;
+pub mod cache;
 pub mod config;
 pub mod db;
 pub mod fetch;
+pub mod guard;
 pub mod helpers;
+pub mod limit;
+pub mod poller;
+pub mod problem;
 pub mod replay;
+pub mod replays;
+pub mod retry;
 pub mod router;
+pub mod stream;
 pub mod summary;
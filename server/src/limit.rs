@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::BoxBody,
+    extract::{ConnectInfo, State},
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hyper::{header, StatusCode};
+
+use crate::{config::Config, helpers::HeaderMapUtils, problem::Problem};
+
+/// The most buckets kept at once. Past this, the ones used least recently
+/// are forgotten.
+const MAX_TRACKED: usize = 10_000;
+
+/// A token bucket per key, each allowing bursts of up to a minute's worth of
+/// requests before settling down to the steady rate.
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    per_minute: u32,
+    max_tracked: usize,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    /// No limit at all if `per_minute` is 0.
+    pub fn new(per_minute: u32) -> Self {
+        RateLimiter {
+            per_minute,
+            max_tracked: MAX_TRACKED,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Uses up one request for `key`, or says how long until there's one to
+    /// spare.
+    pub fn check(&self, key: K) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+        let capacity = f64::from(self.per_minute);
+        let per_second = capacity / 60.0;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().expect("rate limiter poisoned");
+        if buckets.len() >= self.max_tracked && !buckets.contains_key(&key) {
+            forget_oldest(&mut buckets);
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+
+    /// How long until `key` could have a request while still leaving
+    /// `reserve` (a share of the whole bucket) for everyone else. Doesn't use
+    /// anything up.
+    pub fn spare_after(&self, key: &K, reserve: f64) -> Duration {
+        if self.per_minute == 0 {
+            return Duration::ZERO;
+        }
+        let capacity = f64::from(self.per_minute);
+        let per_second = capacity / 60.0;
+        let needed = (1.0 + capacity * reserve).min(capacity);
+        let buckets = self.buckets.lock().expect("rate limiter poisoned");
+        let tokens = buckets
+            .get(key)
+            .map_or(capacity, |bucket| self.refill(bucket, Instant::now()));
+        Duration::from_secs_f64((needed - tokens).max(0.0) / per_second)
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let capacity = f64::from(self.per_minute);
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * capacity / 60.0).min(capacity)
+    }
+}
+
+/// Makes room by forgetting the half of the buckets used least recently, so
+/// it only needs doing every so often rather than for every new key.
+fn forget_oldest<K: Hash + Eq>(buckets: &mut HashMap<K, Bucket>) {
+    let mut updated: Vec<Instant> = buckets.values().map(|bucket| bucket.updated).collect();
+    let middle = updated.len() / 2;
+    let (_, &mut cutoff, _) = updated.select_nth_unstable(middle);
+    buckets.retain(|_, bucket| bucket.updated > cutoff);
+}
+
+/// Limits how often each client can make requests.
+#[derive(Clone, Debug)]
+pub struct ClientLimiter {
+    limiter: Arc<RateLimiter<IpAddr>>,
+    /// Set by a proxy we trust (like `Fly-Client-IP`), since otherwise every
+    /// request would seem to come from the proxy.
+    ip_header: Option<HeaderName>,
+}
+
+impl ClientLimiter {
+    pub fn new(config: &Config) -> Self {
+        ClientLimiter {
+            limiter: Arc::new(RateLimiter::new(config.client_requests_per_minute)),
+            ip_header: config.client_ip_header.as_deref().map(|name| {
+                HeaderName::try_from(name).expect("Invalid client_ip_header in config")
+            }),
+        }
+    }
+
+    fn client_ip<B>(&self, addr: SocketAddr, request: &Request<B>) -> IpAddr {
+        self.ip_header
+            .as_ref()
+            .and_then(|name| request.headers().get_str(name))
+            .and_then(|ip| ip.trim().parse().ok())
+            .unwrap_or_else(|| addr.ip())
+    }
+}
+
+/// Who a client's requests are counted against. IPv6 clients usually get a
+/// whole /64 to themselves, so that's what's counted rather than each
+/// address in it (IPv4 clients showing up as `::ffff:a.b.c.d` are left be).
+fn client_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & !(u64::MAX as u128))),
+        },
+        ip => ip,
+    }
+}
+
+pub async fn limit_clients<B>(
+    State(limiter): State<ClientLimiter>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response<BoxBody> {
+    let ip = limiter.client_ip(addr, &request);
+    match limiter.limiter.check(client_key(ip)) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            tracing::info!("Rate limited {}", ip);
+            too_many_requests(
+                "Too many requests, please slow down".to_string(),
+                retry_after,
+            )
+        }
+    }
+}
+
+/// A 429, saying when to try again.
+pub fn too_many_requests(detail: String, retry_after: Duration) -> Response<BoxBody> {
+    let mut response = Problem::new(StatusCode::TOO_MANY_REQUESTS, detail).into_response();
+    // Rounded up, so trying again right then will work.
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::IpAddr, time::Duration};
+
+    use super::{client_key, RateLimiter};
+
+    #[test]
+    fn allows_a_burst_then_limits() {
+        let limiter = RateLimiter::new(2);
+        assert_eq!(limiter.check("a"), Ok(()));
+        assert_eq!(limiter.check("a"), Ok(()));
+        let retry_after = limiter.check("a").unwrap_err();
+        assert!(retry_after.as_secs() <= 30);
+        assert_eq!(limiter.check("b"), Ok(()));
+    }
+
+    #[test]
+    fn unlimited_when_zero() {
+        let limiter = RateLimiter::new(0);
+        for _ in 0..100 {
+            assert_eq!(limiter.check("a"), Ok(()));
+        }
+    }
+
+    #[test]
+    fn leaves_a_reserve_spare() {
+        let limiter = RateLimiter::new(4);
+        assert_eq!(limiter.spare_after(&"a", 0.5), Duration::ZERO);
+        assert_eq!(limiter.check("a"), Ok(()));
+        assert_eq!(limiter.spare_after(&"a", 0.5), Duration::ZERO);
+        assert_eq!(limiter.check("a"), Ok(()));
+        // Two left, but those are being kept for someone else.
+        let wait = limiter.spare_after(&"a", 0.5);
+        assert!(wait > Duration::from_secs(14) && wait <= Duration::from_secs(15));
+        assert_eq!(limiter.check("a"), Ok(()));
+        assert_eq!(limiter.spare_after(&"b", 0.5), Duration::ZERO);
+        assert_eq!(RateLimiter::new(0).spare_after(&"a", 0.5), Duration::ZERO);
+    }
+
+    #[test]
+    fn never_tracks_more_than_the_limit() {
+        let limiter = RateLimiter {
+            max_tracked: 4,
+            ..RateLimiter::new(1)
+        };
+        for key in 0..100 {
+            assert_eq!(limiter.check(key), Ok(()));
+            assert!(limiter.buckets.lock().unwrap().len() <= 4);
+        }
+        // Whoever was seen most recently is still remembered.
+        assert!(limiter.check(99).is_err());
+    }
+
+    #[test]
+    fn counts_ipv6_clients_by_network() {
+        let key = |ip: &str| client_key(ip.parse::<IpAddr>().unwrap());
+        assert_eq!(key("2001:db8:1:2:3:4:5:6"), key("2001:db8:1:2::1"));
+        assert_ne!(key("2001:db8:1:2::1"), key("2001:db8:1:3::1"));
+        assert_eq!(key("::ffff:192.0.2.1"), key("192.0.2.1"));
+        assert_ne!(key("::ffff:192.0.2.1"), key("::ffff:192.0.2.2"));
+    }
+}
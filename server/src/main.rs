@@ -3,7 +3,9 @@ use std::net::SocketAddr;
 use axum::Server;
 use podreplay::config::Config;
 use podreplay::db::Db;
-use podreplay::fetch::HttpClient;
+use podreplay::fetch::{FetchLimits, HttpClient};
+use podreplay::guard::AddressGuard;
+use podreplay::poller::Poller;
 use podreplay::router::make_router;
 
 #[tokio::main]
@@ -31,7 +33,16 @@ async fn main() {
 
     db.migrate().await.expect("Failed to run migrations");
 
-    let http = HttpClient::new(config.user_agent.clone());
+    let guard = AddressGuard::from_config(&config)
+        .unwrap_or_else(|err| panic!("Invalid fetch_allow or fetch_deny ({})", err));
+    let http = HttpClient::new(config.user_agent.clone(), guard, FetchLimits::from(&config));
+
+    if Poller::new(db.clone(), http.clone(), &config)
+        .spawn()
+        .is_none()
+    {
+        tracing::info!("Feed polling is turned off");
+    }
 
     let app = make_router(db, http, &config);
     let addr = SocketAddr::new(config.host, config.port);
@@ -0,0 +1,183 @@
+#![allow(clippy::result_large_err)]
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use podreplay_lib::FeedMeta;
+use rand::Rng;
+use tokio::{
+    sync::Semaphore,
+    task::{JoinHandle, JoinSet},
+    time::{Instant, MissedTickBehavior},
+};
+
+use crate::{
+    config::Config,
+    db::Db,
+    fetch::{FetchException, HttpClient, Spooled},
+    replay::{
+        body_unchanged, follow_move, get_updated_caches, read_spooled, summarize_spooled,
+        MergeMovedFeeds, ReplayError, StoredFeed,
+    },
+};
+
+/// Checks every known feed in the background, so new and removed items are
+/// noticed close to when it happened rather than whenever someone next asks
+/// for a replay.
+#[derive(Clone)]
+pub struct Poller {
+    db: Db,
+    http: HttpClient,
+    interval: Duration,
+    concurrency: usize,
+    jitter: Duration,
+    merge_moved_feeds: MergeMovedFeeds,
+}
+
+impl Poller {
+    pub fn new(db: Db, http: HttpClient, config: &Config) -> Self {
+        Poller {
+            db,
+            http,
+            interval: Duration::from_secs(config.poll_interval),
+            concurrency: config.poll_concurrency.max(1),
+            jitter: Duration::from_secs(config.poll_jitter),
+            merge_moved_feeds: MergeMovedFeeds::from(config),
+        }
+    }
+
+    /// Polls every `interval` (starting one `interval` from now, since
+    /// whatever's just started up has better things to do) until the task is
+    /// aborted. Returns `None` if polling is turned off.
+    pub fn spawn(self) -> Option<JoinHandle<()>> {
+        if self.interval.is_zero() {
+            return None;
+        }
+        Some(tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval_at(Instant::now() + self.interval, self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.poll_all().await {
+                    tracing::error!("Failed to poll feeds: {}", err);
+                }
+            }
+        }))
+    }
+
+    /// Fetches every known feed once, `concurrency` at a time. Failures for
+    /// individual feeds are logged and otherwise ignored.
+    pub async fn poll_all(&self) -> Result<(), sqlx::Error> {
+        let feeds = self.db.get_feeds().await?;
+        tracing::debug!("Polling {} feed(s)", feeds.len());
+
+        let permits = Arc::new(Semaphore::new(self.concurrency));
+        let mut tasks = JoinSet::new();
+        for feed in feeds {
+            let poller = self.clone();
+            let permits = permits.clone();
+            let delay = rand::thread_rng().gen_range(Duration::ZERO..=self.jitter);
+            tasks.spawn(async move {
+                tokio::time::sleep(delay).await;
+                let _permit = permits.acquire().await.expect("semaphore closed");
+                if let Err(err) = poller.poll(&feed).await {
+                    tracing::warn!("Failed to poll {}: {}", feed.uri, err);
+                }
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            if let Err(err) = result {
+                tracing::error!("Polling task failed: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self, feed), fields(uri = %feed.uri))]
+    async fn poll(&self, feed: &FeedMeta) -> Result<(), ReplayError> {
+        // Rather than being turned away, and before taking `now` so it's when
+        // the fetch actually happened.
+        self.http.wait_for_origin(&feed.uri).await;
+        let now = Utc::now();
+        let fetched = self
+            .http
+            .get_spooled(&feed.uri, feed.etag.clone(), feed.last_modified.clone())
+            .await;
+        let fetched = match fetched {
+            Ok(fetched) => fetched,
+            Err(FetchException::NotModified(_)) => {
+                tracing::debug!("Not modified");
+                self.db
+                    .update_feed_meta(&feed.uri, &now, &feed.etag, &feed.last_modified)
+                    .await?;
+                self.db.touch_feed_body(&feed.uri, &now, &feed.etag).await?;
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let Spooled {
+            file,
+            etag: fetched_etag,
+            last_modified: fetched_last_modified,
+            content_type,
+            moved,
+            refresh,
+            ..
+        } = fetched;
+        let Some(refresh) = refresh else {
+            tracing::debug!("Already fetched by someone else");
+            return Ok(());
+        };
+        let unchanged = body_unchanged(&self.db, &feed.uri, &fetched_etag, &file).await?;
+        let uri = feed.uri.clone();
+        let (summary, body) = tokio::task::spawn_blocking(move || {
+            let mut file = file;
+            let body = (!unchanged).then(|| read_spooled(&mut file)).transpose()?;
+            let (summary, _) = summarize_spooled(uri, file, false)?;
+            Ok::<_, ReplayError>((summary, body))
+        })
+        .await??;
+        let (feed_meta, _) = get_updated_caches(
+            self.db.clone(),
+            &feed.uri,
+            now,
+            &fetched_etag,
+            &fetched_last_modified,
+            &summary,
+        )
+        .await?;
+        match body {
+            Some(body) => {
+                self.db
+                    .save_feed_body(&StoredFeed {
+                        feed_id: feed_meta.id,
+                        fetched: now,
+                        etag: fetched_etag.clone(),
+                        last_modified: fetched_last_modified,
+                        content_type,
+                        body,
+                    })
+                    .await?
+            }
+            None => {
+                self.db
+                    .touch_feed_body(&feed.uri, &now, &fetched_etag)
+                    .await?
+            }
+        }
+        follow_move(
+            &self.db,
+            &self.http,
+            &feed.uri,
+            &fetched_etag,
+            moved,
+            &summary,
+            self.merge_moved_feeds,
+        )
+        .await?;
+        refresh.finish();
+        Ok(())
+    }
+}
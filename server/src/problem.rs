@@ -0,0 +1,57 @@
+use axum::{
+    body::BoxBody,
+    response::{IntoResponse, Response},
+};
+use hyper::{header, StatusCode};
+use podreplay_lib::ParseError;
+use serde::Serialize;
+
+/// An RFC 7807 problem document, so callers get something more useful than a
+/// bare status code when a feed we fetched on their behalf is broken.
+#[derive(Serialize, Debug)]
+pub struct Problem<'a> {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    #[serde(flatten)]
+    parse_error: Option<&'a ParseError>,
+}
+
+impl<'a> Problem<'a> {
+    pub fn new(status: StatusCode, detail: String) -> Self {
+        Problem {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Unknown"),
+            status: status.as_u16(),
+            detail,
+            parse_error: None,
+        }
+    }
+
+    pub fn feed_parse_error(error: &'a ParseError) -> Self {
+        Problem {
+            parse_error: Some(error),
+            ..Problem::new(StatusCode::BAD_GATEWAY, error.to_string())
+        }
+    }
+}
+
+impl<'a> IntoResponse for Problem<'a> {
+    fn into_response(self) -> Response<BoxBody> {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::BAD_GATEWAY);
+        match serde_json::to_vec(&self) {
+            Ok(body) => (
+                status,
+                [(header::CONTENT_TYPE, "application/problem+json")],
+                body,
+            )
+                .into_response(),
+            Err(err) => {
+                tracing::error!("Failed to serialize problem: {}", err);
+                status.into_response()
+            }
+        }
+    }
+}
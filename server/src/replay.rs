@@ -1,28 +1,37 @@
-#![allow(clippy::large_enum_variant)]
+#![allow(clippy::large_enum_variant, clippy::result_large_err)]
 
-use std::net::SocketAddr;
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+};
 
 use axum::{
-    body::{Body, BoxBody},
-    extract::{ConnectInfo, Extension, Query},
+    body::{boxed, Body, BoxBody},
+    extract::{Extension, Query},
     response::IntoResponse,
 };
-use chrono::{DateTime, SecondsFormat, Utc};
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
 use headers::{HeaderMap, HeaderValue};
 use hyper::{Request, Response, StatusCode};
 use lazy_static::lazy_static;
 use podreplay_lib::{
-    create_cached_entry_map, diff_feed, parse_rule, parse_timestamp, reschedule_feed, rewrite_feed,
-    FeedSummary, RewriteError, SummarizeError,
+    create_cached_entry_map, diff_feed, parse_rule, parse_timestamp, plan_feed, project_feed,
+    replay_calendar, reschedule_feed, rewrite_feed_lenient, rewrite_feed_to_writer, FeedOverrides,
+    FeedSummary, NotePosition, ReplayStatus, Reschedule, RewriteError, RewriteOptions,
+    SummarizeError, TitleTemplate,
 };
 use regex::Regex;
 use serde::Deserialize;
 use thiserror::Error;
+use url::{form_urlencoded, Url};
 
 use crate::{
+    config::Config,
     db::Db,
-    fetch::{FetchException, HttpClient},
+    fetch::{FetchException, HttpClient, Spooled},
     helpers::HeaderMapUtils,
+    problem::Problem,
+    stream::BodyWriter,
 };
 
 #[derive(Deserialize, Debug)]
@@ -33,15 +42,67 @@ pub struct ReplayQuery {
     last: Option<DateTime<Utc>>,
     uri: String,
     title: Option<String>,
+    description: Option<String>,
+    image: Option<String>,
+    author: Option<String>,
+    title_template: Option<String>,
     now: Option<DateTime<Utc>>,
+    #[serde(default)]
+    lenient: bool,
+    #[serde(default)]
+    sort: bool,
+    #[serde(default)]
+    status: bool,
+    #[serde(default)]
+    faithful: bool,
+    original_date: Option<NotePosition>,
+    #[serde(default)]
+    format: ReplayFormat,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayFormat {
+    /// The rewritten feed itself.
+    #[default]
+    Rss,
+    /// A calendar of when each item was or will be replayed.
+    Ics,
+    /// The same as the calendar, as a `Plan`.
+    Json,
 }
 
 #[tracing::instrument]
 pub async fn get<'a>(
-    query: Query<ReplayQuery>,
+    Query(query): Query<ReplayQuery>,
     Extension(db): Extension<Db>,
     Extension(http): Extension<HttpClient>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(max_staleness): Extension<MaxStaleness>,
+    Extension(merge_moved_feeds): Extension<MergeMovedFeeds>,
+    Extension(public_base_url): Extension<PublicBaseUrl>,
+    request: Request<Body>,
+) -> Result<Replay, ReplayError> {
+    replay(
+        query,
+        db,
+        http,
+        max_staleness,
+        merge_moved_feeds,
+        public_base_url,
+        request,
+    )
+    .await
+}
+
+/// Everything behind `get`, for anything else that ends up with a
+/// `ReplayQuery` (see `replays::get`).
+pub async fn replay(
+    query: ReplayQuery,
+    db: Db,
+    http: HttpClient,
+    max_staleness: MaxStaleness,
+    merge_moved_feeds: MergeMovedFeeds,
+    public_base_url: PublicBaseUrl,
     request: Request<Body>,
 ) -> Result<Replay, ReplayError> {
     let clock_now = Utc::now();
@@ -51,6 +112,12 @@ pub async fn get<'a>(
             "Please don't specify a ?now beyond 1 year".to_string(),
         ));
     }
+    let title_template = query
+        .title_template
+        .as_deref()
+        .map(TitleTemplate::parse)
+        .transpose()
+        .map_err(|err| ReplayError::InvalidRequest(format!("Invalid title_template: {err}")))?;
 
     let headers = request.headers();
 
@@ -62,40 +129,249 @@ pub async fn get<'a>(
         if now < expires {
             tracing::debug!("NotModified ({} < {:?})", now, expires);
             return Err(ReplayError::NotModified {
-                headers: prepare_headers(request_expires, feed_request_etag.map(|e| e.to_string())),
+                headers: prepare_headers(
+                    request_expires,
+                    feed_request_etag.map(|e| e.to_string()),
+                    None,
+                    false,
+                ),
             });
         } else {
             tracing::debug!("NotModified {:?}", request_expires);
         }
     }
 
-    let fetched = http
-        .get(
-            &query.uri,
-            feed_request_etag.map(|etag| format!(r#""{etag}""#)),
-        )
+    // Our Last-Modified dates aren't the origin's, so this only ever gets
+    // answered once we know when the replay itself last changed.
+    let if_modified_since = if if_none_match.is_none() {
+        headers
+            .get_str("if-modified-since")
+            .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+            .map(DateTime::<Utc>::from)
+    } else {
+        None
+    };
+
+    // Wherever it's moved to since, if it has.
+    let feed_uri = db.resolve_feed_uri(&query.uri).await?;
+
+    // Without an etag from the client we can still ask with whatever our
+    // stored copy was fetched with, and answer from that if nothing changed.
+    let (origin_etag, origin_last_modified) = match feed_request_etag {
+        Some(etag) => (Some(format!(r#""{etag}""#)), None),
+        None => db.get_feed_body_validators(&feed_uri).await?,
+    };
+    let mut fetched = http
+        .get_spooled(&feed_uri, origin_etag.clone(), origin_last_modified)
         .await;
+    let mut stored = None;
+    if feed_request_etag.is_none() && matches!(fetched, Err(FetchException::NotModified(_))) {
+        stored = db.get_feed_body(&feed_uri).await?;
+        if stored.is_none() {
+            // Gone since we asked, so there's nothing to answer from but a
+            // whole new copy.
+            fetched = http.get_spooled(&feed_uri, None, None).await;
+        }
+    }
 
-    let fetched = match fetched {
-        Ok(fetched) => fetched,
-        Err(FetchException::NotModified(_)) => {
+    let mut stale = false;
+    let (file, fetched_etag, fetched_last_modified, content_type, moved, refresh) = match fetched {
+        Ok(Spooled {
+            file,
+            etag,
+            last_modified,
+            content_type,
+            moved,
+            refresh,
+            ..
+        }) => (file, etag, last_modified, content_type, moved, refresh),
+        Err(FetchException::NotModified(_)) if feed_request_etag.is_some() => {
+            db.touch_feed_body(&feed_uri, &clock_now, &origin_etag)
+                .await?;
             return Err(ReplayError::NotModified {
-                headers: prepare_headers(request_expires, feed_request_etag.map(|e| e.to_string())),
+                headers: prepare_headers(
+                    request_expires,
+                    feed_request_etag.map(|e| e.to_string()),
+                    None,
+                    false,
+                ),
             });
         }
-        err => err?,
+        Err(FetchException::NotModified(_)) => {
+            db.touch_feed_body(&feed_uri, &clock_now, &origin_etag)
+                .await?;
+            let stored = stored.ok_or(FetchException::Unknown)?;
+            let file = stored_file(&stored.body)?;
+            let StoredFeed {
+                etag,
+                last_modified,
+                content_type,
+                ..
+            } = stored;
+            (file, etag, last_modified, content_type, None, None)
+        }
+        Err(err) if err.is_unavailable() => match db.get_feed_body(&feed_uri).await? {
+            Some(stored) if clock_now - stored.fetched <= max_staleness.0 => {
+                tracing::warn!(
+                    "Serving {} as of {} instead ({})",
+                    feed_uri,
+                    stored.fetched,
+                    err
+                );
+                stale = true;
+                let file = stored_file(&stored.body)?;
+                let StoredFeed {
+                    etag,
+                    last_modified,
+                    content_type,
+                    ..
+                } = stored;
+                (file, etag, last_modified, content_type, None, None)
+            }
+            _ => return Err(err.into()),
+        },
+        Err(err) => return Err(err.into()),
     };
 
-    let summary = FeedSummary::new(query.uri.clone(), &fetched.body)?;
+    let uri = feed_uri.clone();
+    let lenient = query.lenient;
+    let fresh = refresh.is_some();
+    let unchanged = fresh && body_unchanged(&db, &feed_uri, &fetched_etag, &file).await?;
+    let save_body = fresh && !unchanged;
+    let (summary, spooled, body) = tokio::task::spawn_blocking(move || {
+        let mut file = file;
+        let body = save_body.then(|| read_spooled(&mut file)).transpose()?;
+        let (summary, spooled) = summarize_spooled(uri, file, lenient)?;
+        Ok::<_, ReplayError>((summary, spooled, body))
+    })
+    .await??;
+    if !summary.repairs.is_empty() {
+        tracing::warn!(
+            "Repaired {} problem(s) in {}: {:?}",
+            summary.repairs.len(),
+            feed_uri,
+            summary.repairs
+        );
+    }
 
-    let (feed_meta, entries) =
-        get_updated_caches(db, &query.uri, now, &fetched.etag, &summary).await?;
+    // Only what just came from the origin can have anything new in it.
+    // Everything else already went through here when it was fetched.
+    let known_feed = if fresh {
+        None
+    } else {
+        db.get_feed(&feed_uri).await?
+    };
+    let (feed_meta, entries) = match known_feed {
+        Some(feed_meta) => {
+            let entries = db.get_entries(feed_meta.id).await?;
+            (feed_meta, entries)
+        }
+        None => {
+            get_updated_caches(
+                db.clone(),
+                &feed_uri,
+                now,
+                &fetched_etag,
+                &fetched_last_modified,
+                &summary,
+            )
+            .await?
+        }
+    };
+    if let Some(body) = body {
+        db.save_feed_body(&StoredFeed {
+            feed_id: feed_meta.id,
+            fetched: clock_now,
+            etag: fetched_etag.clone(),
+            last_modified: fetched_last_modified.clone(),
+            content_type: content_type.clone(),
+            body,
+        })
+        .await?;
+    } else if unchanged {
+        db.touch_feed_body(&feed_uri, &clock_now, &fetched_etag)
+            .await?;
+    }
+    if fresh {
+        follow_move(
+            &db,
+            &http,
+            &feed_uri,
+            &fetched_etag,
+            moved,
+            &summary,
+            merge_moved_feeds,
+        )
+        .await?;
+    }
+    // Anyone who was waiting on this fetch can have it now.
+    if let Some(refresh) = refresh {
+        refresh.finish();
+    }
 
     let query_start = parse_timestamp(&query.start).ok_or_else(|| {
         ReplayError::InvalidRequest(format!("Unable to parse timestamp {}", query.start))
     })?;
     let rule = parse_rule(query_start, &query.rule);
 
+    match query.format {
+        ReplayFormat::Rss => {}
+        ReplayFormat::Ics => {
+            let (replayed, next_slot, upcoming) = project_feed(
+                &entries,
+                rule,
+                query_start,
+                now,
+                feed_meta.first_fetched,
+                query.first,
+                query.last,
+            );
+            let last_modified =
+                replay_last_modified(&fetched_last_modified, replayed.values().copied());
+            let mut headers = prepare_headers(next_slot, fetched_etag, last_modified, stale);
+            check_modified_since(if_modified_since, last_modified, &headers)?;
+            let name = query
+                .title
+                .clone()
+                .unwrap_or_else(|| format!("{} (PodReplay)", summary.title));
+            let calendar = replay_calendar(&name, &summary, &replayed, &upcoming, now);
+            headers.append(
+                "Content-Type",
+                HeaderValue::from_static("text/calendar; charset=utf-8"),
+            );
+            return Ok(Replay {
+                body: Body::from(calendar),
+                headers,
+            });
+        }
+        ReplayFormat::Json => {
+            let plan = plan_feed(
+                &entries,
+                rule,
+                query_start,
+                now,
+                feed_meta.first_fetched,
+                query.first,
+                query.last,
+            );
+            let last_modified = replay_last_modified(
+                &fetched_last_modified,
+                plan.items
+                    .iter()
+                    .filter(|item| !item.projected)
+                    .map(|item| item.date),
+            );
+            let mut headers = prepare_headers(plan.next_slot, fetched_etag, last_modified, stale);
+            check_modified_since(if_modified_since, last_modified, &headers)?;
+            headers.append("Content-Type", HeaderValue::from_static("application/json"));
+            let body = serde_json::to_vec(&plan).map_err(std::io::Error::from)?;
+            return Ok(Replay {
+                body: Body::from(body),
+                headers,
+            });
+        }
+    }
+
     let (replayed, next_slot) = reschedule_feed(
         &entries,
         rule,
@@ -105,35 +381,326 @@ pub async fn get<'a>(
         query.first,
         query.last,
     );
+    let last_modified = replay_last_modified(&fetched_last_modified, replayed.values().copied());
+    let mut headers = prepare_headers(next_slot, fetched_etag, last_modified, stale);
+    check_modified_since(if_modified_since, last_modified, &headers)?;
 
-    let body = rewrite_feed(
-        &fetched.body,
-        &replayed,
-        true,
-        !summary.marked_private,
-        &query.title,
-    )?;
-    let mut headers = prepare_headers(next_slot, fetched.etag);
-    headers.append(
-        "Content-Type",
-        HeaderValue::from_str(
-            &fetched
-                .content_type
-                .unwrap_or_else(|| "application/rss+xml".to_string()),
-        )
-        .unwrap(),
-    );
+    let options = RewriteOptions {
+        pretty: true,
+        faithful: query.faithful,
+        mark_as_private: !summary.marked_private,
+        overrides: FeedOverrides {
+            title: query.title.clone(),
+            description: query.description.clone(),
+            image: query.image.clone(),
+            author: query.author.clone(),
+        },
+        now,
+        next_slot,
+        sort_items: query.sort,
+        self_url: public_url(&request, &public_base_url),
+        item_titles: title_template
+            .map(|template| template.render_titles(&summary, &replayed))
+            .unwrap_or_default(),
+        status: query.status.then(|| {
+            let total = summary
+                .items
+                .iter()
+                .filter(|item| query.first.map_or(true, |first| item.timestamp >= first))
+                .filter(|item| query.last.map_or(true, |last| item.timestamp <= last))
+                .count();
+            ReplayStatus::new(&replayed, next_slot, total)
+        }),
+        original_date_note: query.original_date,
+    };
+    let body = match spooled {
+        SpooledFeed::File(file) => stream_rewrite(file, replayed, options)?,
+        // We had to repair it, so we may as well finish the job in memory.
+        SpooledFeed::Loaded(xml) => {
+            let (body, _) = rewrite_feed_lenient(&xml, &replayed, &options)?;
+            Body::from(body)
+        }
+    };
+    // Whatever the origin said it was, as long as that can be sent on as is.
+    let content_type = content_type
+        .and_then(|content_type| HeaderValue::from_str(&content_type).ok())
+        .unwrap_or_else(|| HeaderValue::from_static("application/rss+xml"));
+    headers.append("Content-Type", content_type);
     Ok(Replay { body, headers })
 }
 
-async fn get_updated_caches(
+/// The URL this replay was requested at, as the client saw it, so the feed can
+/// point back to itself. `now` is left out since it's only for testing and
+/// would otherwise freeze the replay for anyone following the link.
+fn public_url(request: &Request<Body>, base: &PublicBaseUrl) -> Option<String> {
+    let base = match &base.0 {
+        Some(base) => base.clone(),
+        None => {
+            let headers = request.headers();
+            let host = headers.get_str("host")?;
+            let scheme = headers.get_str("x-forwarded-proto").unwrap_or("http");
+            format!("{scheme}://{host}")
+        }
+    };
+    let mut url = Url::parse(&format!("{base}{}", request.uri().path())).ok()?;
+    if let Some(query) = request.uri().query() {
+        let pairs = form_urlencoded::parse(query.as_bytes()).filter(|(key, _)| key != "now");
+        url.query_pairs_mut().extend_pairs(pairs);
+    }
+    Some(url.into())
+}
+
+/// The last copy of a feed that could be summarized, to fall back on when the
+/// origin is having trouble.
+#[derive(Debug)]
+pub struct StoredFeed {
+    pub feed_id: i64,
+    pub fetched: DateTime<Utc>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// How old a `StoredFeed` can be and still be served in place of the origin.
+#[derive(Clone, Copy, Debug)]
+pub struct MaxStaleness(pub chrono::Duration);
+
+impl From<&Config> for MaxStaleness {
+    fn from(config: &Config) -> Self {
+        MaxStaleness(chrono::Duration::seconds(config.max_staleness as i64))
+    }
+}
+
+/// Where replays are linked to from (see `Config::public_base_url`), without
+/// a trailing slash.
+#[derive(Clone, Debug)]
+pub struct PublicBaseUrl(pub Option<String>);
+
+impl From<&Config> for PublicBaseUrl {
+    fn from(config: &Config) -> Self {
+        PublicBaseUrl(config.public_base_url.as_deref().map(|url| {
+            Url::parse(url).expect("Invalid public_base_url in config");
+            url.trim_end_matches('/').to_string()
+        }))
+    }
+}
+
+/// Whether a feed that moves somewhere we already have a history for gets
+/// merged with it (see `Db::move_feed`).
+#[derive(Clone, Copy, Debug)]
+pub struct MergeMovedFeeds(pub bool);
+
+impl From<&Config> for MergeMovedFeeds {
+    fn from(config: &Config) -> Self {
+        MergeMovedFeeds(config.merge_moved_feeds)
+    }
+}
+
+/// Follows a feed to wherever it's permanently moved, either by redirecting
+/// there or by saying so with `itunes:new-feed-url`, so its history carries
+/// on under the new address. Any feed can say it's moved anywhere, so that's
+/// only taken at its word if there's a feed there that agrees (see
+/// `confirms_move`), and is never merged into a history of its own.
+pub(crate) async fn follow_move(
+    db: &Db,
+    http: &HttpClient,
+    uri: &str,
+    etag: &Option<String>,
+    redirected: Option<Url>,
+    summary: &FeedSummary,
+    merge: MergeMovedFeeds,
+) -> Result<(), ReplayError> {
+    let declared = summary
+        .new_feed_url
+        .as_deref()
+        .and_then(|url| Url::parse(url).ok())
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .filter(|url| Url::parse(uri).ok().as_ref() != Some(url));
+    let (to, merge) = match (redirected, declared) {
+        (Some(to), _) => (to, merge.0),
+        (None, Some(to)) => {
+            // Checking means fetching `to`, so it isn't done again until the
+            // feed (or where it says it's gone) changes.
+            if db.is_unconfirmed_move(uri, to.as_str(), etag).await? {
+                return Ok(());
+            }
+            if !confirms_move(http, uri, &to).await? {
+                db.record_unconfirmed_move(uri, to.as_str(), etag, &Utc::now())
+                    .await?;
+                return Ok(());
+            }
+            (to, false)
+        }
+        _ => return Ok(()),
+    };
+    if db.move_feed(uri, to.as_str(), &Utc::now(), merge).await? {
+        tracing::info!("Moved {} to {}", uri, to);
+    } else {
+        tracing::info!("Not moving {} to {}, which has its own history", uri, to);
+    }
+    Ok(())
+}
+
+/// Whether there's a feed at `to` that hasn't itself moved on (back to `from`
+/// or anywhere else).
+async fn confirms_move(http: &HttpClient, from: &str, to: &Url) -> Result<bool, ReplayError> {
+    let fetched = match http.get(to.as_str(), None, None).await {
+        Ok(fetched) => fetched,
+        Err(err) => {
+            tracing::info!("Not moving {} to {}, which failed ({})", from, to, err);
+            return Ok(false);
+        }
+    };
+    let uri = to.to_string();
+    let summary =
+        tokio::task::spawn_blocking(move || FeedSummary::from_reader(uri, &fetched.body[..]))
+            .await?;
+    match summary {
+        Ok(summary) => match summary.new_feed_url {
+            Some(url) if Url::parse(&url).ok().as_ref() != Some(to) => {
+                tracing::info!("Not moving {} to {}, which moved to {}", from, to, url);
+                Ok(false)
+            }
+            _ => Ok(true),
+        },
+        Err(err) => {
+            tracing::info!(
+                "Not moving {} to {}, which isn't a feed ({})",
+                from,
+                to,
+                err
+            );
+            Ok(false)
+        }
+    }
+}
+
+/// Whether the stored copy is the same as the one just fetched, going by its
+/// etag and size, so it needn't be read into memory and written again.
+pub(crate) async fn body_unchanged(
+    db: &Db,
+    uri: &str,
+    etag: &Option<String>,
+    file: &File,
+) -> Result<bool, ReplayError> {
+    let Some(etag) = etag else {
+        return Ok(false);
+    };
+    let len = file.metadata()?.len() as i64;
+    Ok(db.has_feed_body(uri, etag, len).await?)
+}
+
+/// Puts a stored copy back in a file, so it goes through the same path as a
+/// freshly fetched one.
+fn stored_file(body: &[u8]) -> Result<File, std::io::Error> {
+    let mut file = tempfile::tempfile()?;
+    file.write_all(body)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+pub(crate) fn read_spooled(file: &mut File) -> Result<Vec<u8>, std::io::Error> {
+    read_spooled_prefix(file, u64::MAX)
+}
+
+fn read_spooled_prefix(file: &mut File, limit: u64) -> Result<Vec<u8>, std::io::Error> {
+    let mut body = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    (&*file).take(limit).read_to_end(&mut body)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(body)
+}
+
+pub(crate) enum SpooledFeed {
+    File(File),
+    Loaded(Vec<u8>),
+}
+
+/// Repairing a feed means holding it (and a copy or two) in memory, so past
+/// this size a broken one is reported rather than repaired.
+const MAX_REPAIR_SIZE: u64 = 8 * 1024 * 1024;
+
+/// How much of the feed past a parse error we read to finish off its line.
+const CONTEXT_AFTER_ERROR: u64 = 4 * 1024;
+
+#[derive(Error, Debug)]
+pub(crate) enum SpooledError {
+    #[error("{0}")]
+    Summarize(#[from] SummarizeError),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<SpooledError> for ReplayError {
+    fn from(err: SpooledError) -> Self {
+        match err {
+            SpooledError::Summarize(err) => ReplayError::ParseError(err),
+            SpooledError::Io(err) => ReplayError::UnknownError(err),
+        }
+    }
+}
+
+/// Summarizes the feed straight from disk. If that fails, we only read as
+/// much of it back into memory as it takes to say where it went wrong, or
+/// the whole thing if we've been asked to repair it and it isn't too big.
+pub(crate) fn summarize_spooled(
+    uri: String,
+    mut file: File,
+    lenient: bool,
+) -> Result<(FeedSummary, SpooledFeed), SpooledError> {
+    let err = match FeedSummary::from_reader(uri.clone(), BufReader::new(&file)) {
+        Ok(summary) => return Ok((summary, SpooledFeed::File(file))),
+        Err(err) => err,
+    };
+    if lenient {
+        let len = file.metadata()?.len();
+        if len <= MAX_REPAIR_SIZE {
+            let xml = read_spooled(&mut file)?;
+            let summary = FeedSummary::new_lenient(uri, &xml)?;
+            return Ok((summary, SpooledFeed::Loaded(xml)));
+        }
+        tracing::warn!("Not repairing {} since it's {} bytes", uri, len);
+    }
+    let position = err.parse_error().map_or(0, |err| err.position as u64);
+    let xml = read_spooled_prefix(&mut file, position.saturating_add(CONTEXT_AFTER_ERROR))?;
+    Err(err.with_context(&xml).into())
+}
+
+/// Rewrites the feed on a blocking thread, sending it out as it's written.
+fn stream_rewrite(
+    mut file: File,
+    replayed: Reschedule<String>,
+    options: RewriteOptions,
+) -> Result<Body, ReplayError> {
+    file.seek(SeekFrom::Start(0))?;
+    let (mut writer, body) = BodyWriter::channel();
+    tokio::task::spawn_blocking(move || {
+        let result = rewrite_feed_to_writer(BufReader::new(file), &mut writer, &replayed, &options);
+        // The summary already made it through the same feed, so this should
+        // be rare. The headers are long gone, so all we can do is cut it off.
+        let result = result
+            .map_err(ReplayError::from)
+            .and_then(|_| Ok(writer.flush()?));
+        if let Err(err) = result {
+            tracing::error!("Failed to stream replay: {}", err);
+            writer.abort();
+        }
+    });
+    Ok(body)
+}
+
+pub(crate) async fn get_updated_caches(
     db: Db,
     uri: &str,
     now: DateTime<Utc>,
     fetched_etag: &Option<String>,
+    fetched_last_modified: &Option<String>,
     feed: &FeedSummary,
 ) -> Result<(podreplay_lib::FeedMeta, Vec<podreplay_lib::CachedEntry>), ReplayError> {
-    let feed_meta = db.update_feed_meta(uri, &now, fetched_etag).await?;
+    let feed_meta = db
+        .update_feed_meta(uri, &now, fetched_etag, fetched_last_modified)
+        .await?;
 
     let cached_entries = db.get_entries(feed_meta.id).await?;
     let cached_entry_map = create_cached_entry_map(&cached_entries);
@@ -148,12 +715,28 @@ async fn get_updated_caches(
     Ok((feed_meta, entries))
 }
 
-fn prepare_headers(next_slot: Option<DateTime<Utc>>, fetched_etag: Option<String>) -> HeaderMap {
+fn prepare_headers(
+    next_slot: Option<DateTime<Utc>>,
+    fetched_etag: Option<String>,
+    last_modified: Option<DateTime<Utc>>,
+    stale: bool,
+) -> HeaderMap {
     // TODO: forward on any other safe/relevant feed caching related headers?
     let mut headers = HeaderMap::new();
+    if stale {
+        headers.insert(
+            "Warning",
+            HeaderValue::from_static(r#"110 - "Response is Stale""#),
+        );
+    }
     if let Some(expires) = next_slot.and_then(|dt| HeaderValue::from_str(&dt.to_rfc2822()).ok()) {
         headers.insert("Expires", expires);
     }
+    if let Some(last_modified) = last_modified
+        .and_then(|dt| HeaderValue::from_str(&dt.format(HTTP_DATE_FORMAT).to_string()).ok())
+    {
+        headers.insert("Last-Modified", last_modified);
+    }
     if let Some(feed_etag) = fetched_etag.and_then(|e| Some(extract_etag_value(&e)?.to_string())) {
         let etag = if let Some(next_dt) =
             next_slot.map(|dt| dt.to_rfc3339_opts(SecondsFormat::Secs, true))
@@ -169,10 +752,45 @@ fn prepare_headers(next_slot: Option<DateTime<Utc>>, fetched_etag: Option<String
     headers
 }
 
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
 lazy_static! {
     static ref ETAG_RE: Regex = Regex::new(r#"^(?:W/)?"?([^"]+)"?$"#).unwrap();
 }
 
+/// When the replay last changed, for `Last-Modified`. That's either when the
+/// feed itself did or the most recent slot, whichever is later, just as the
+/// etag changes with either. Only given if the origin gives us a date, since
+/// otherwise changes to the feed wouldn't show up at all.
+fn replay_last_modified(
+    fetched_last_modified: &Option<String>,
+    slots: impl Iterator<Item = DateTime<Utc>>,
+) -> Option<DateTime<Utc>> {
+    let feed_modified: DateTime<Utc> =
+        DateTime::parse_from_rfc2822(fetched_last_modified.as_deref()?)
+            .ok()?
+            .into();
+    let last_modified = slots.fold(feed_modified, |latest, slot| latest.max(slot));
+    // HTTP dates only go down to the second.
+    Some(last_modified.trunc_subsecs(0))
+}
+
+fn check_modified_since(
+    if_modified_since: Option<DateTime<Utc>>,
+    last_modified: Option<DateTime<Utc>>,
+    headers: &HeaderMap,
+) -> Result<(), ReplayError> {
+    match (if_modified_since, last_modified) {
+        (Some(since), Some(modified)) if modified <= since => {
+            tracing::debug!("NotModified since {} ({})", since, modified);
+            Err(ReplayError::NotModified {
+                headers: headers.clone(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
 fn parse_rfc3339(dt: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
     Ok(DateTime::parse_from_rfc3339(dt)?.into())
 }
@@ -206,12 +824,12 @@ fn parse_request_etag(if_none_match: &str) -> (Option<&str>, Option<DateTime<Utc
 
 pub struct Replay {
     headers: HeaderMap,
-    body: Vec<u8>,
+    body: Body,
 }
 
 impl IntoResponse for Replay {
     fn into_response(self) -> Response<BoxBody> {
-        (self.headers, self.body).into_response()
+        (self.headers, boxed(self.body)).into_response()
     }
 }
 
@@ -229,6 +847,8 @@ pub enum ReplayError {
     DatabaseError(#[from] sqlx::Error),
     #[error("Unexpected internal error")]
     UnknownError(#[from] std::io::Error),
+    #[error("Unexpected internal error")]
+    TaskError(#[from] tokio::task::JoinError),
     #[error("Not modified")]
     NotModified { headers: HeaderMap },
 }
@@ -239,7 +859,14 @@ impl IntoResponse for ReplayError {
         match self {
             Self::NotModified { headers } => (headers, StatusCode::NOT_MODIFIED).into_response(),
             Self::InvalidRequest(message) => (StatusCode::BAD_REQUEST, message).into_response(),
-            Self::FetchError(_) | Self::ParseError(_) => StatusCode::BAD_GATEWAY.into_response(),
+            Self::ParseError(err) => match err.parse_error() {
+                Some(parse_error) => Problem::feed_parse_error(parse_error).into_response(),
+                None => StatusCode::BAD_GATEWAY.into_response(),
+            },
+            Self::WriteError(RewriteError::Parse(err)) => {
+                Problem::feed_parse_error(&err).into_response()
+            }
+            Self::FetchError(err) => err.into_response(),
             _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
         }
     }
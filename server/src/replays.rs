@@ -0,0 +1,259 @@
+#![allow(clippy::result_large_err)]
+
+use axum::{
+    body::{Body, BoxBody},
+    extract::{Extension, Path, Query},
+    headers::{authorization::Bearer, Authorization},
+    http::Uri,
+    response::{IntoResponse, Response},
+    Json, TypedHeader,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::{Request, StatusCode};
+use podreplay_lib::{parse_timestamp, Rule};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::{form_urlencoded, Url};
+
+use crate::{
+    db::Db,
+    fetch::HttpClient,
+    replay::{
+        self, MaxStaleness, MergeMovedFeeds, PublicBaseUrl, Replay, ReplayError, ReplayQuery,
+    },
+};
+
+const SLUG_LENGTH: usize = 8;
+const EDIT_TOKEN_LENGTH: usize = 32;
+
+/// A replay saved under a short slug, so it can be subscribed to at
+/// `/r/{slug}` rather than with all of this in the query string.
+#[derive(Debug)]
+pub struct StoredReplay {
+    pub slug: String,
+    /// See `hash_token`.
+    pub edit_token_hash: String,
+    pub uri: String,
+    pub rule: String,
+    pub start: String,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    pub title: Option<String>,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+/// What can be saved, with the same meaning as in `ReplayQuery`.
+#[derive(Deserialize, Debug)]
+pub struct ReplayDefinition {
+    uri: String,
+    rule: String,
+    start: String,
+    first: Option<DateTime<Utc>>,
+    last: Option<DateTime<Utc>>,
+    title: Option<String>,
+}
+
+impl ReplayDefinition {
+    fn validate(&self) -> Result<(), ReplaysError> {
+        Url::parse(&self.uri)
+            .map_err(|err| ReplaysError::InvalidRequest(format!("Invalid uri: {err}")))?;
+        let start = parse_timestamp(&self.start).ok_or_else(|| {
+            ReplaysError::InvalidRequest(format!("Unable to parse timestamp {}", self.start))
+        })?;
+        // A replay falls back to weekly if it can't make sense of the rule,
+        // which is better caught now than noticed weeks later.
+        Rule::parse(start, &self.rule).map_err(|_| {
+            ReplaysError::InvalidRequest(format!("Unable to parse rule {}", self.rule))
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Created {
+    slug: String,
+    path: String,
+    /// Needed to change the replay later, and only ever given out here.
+    edit_token: String,
+}
+
+#[tracing::instrument(skip(db))]
+pub async fn create(
+    Extension(db): Extension<Db>,
+    Json(definition): Json<ReplayDefinition>,
+) -> Result<(StatusCode, Json<Created>), ReplaysError> {
+    definition.validate()?;
+    let now = Utc::now();
+    let edit_token = random_string(EDIT_TOKEN_LENGTH);
+    let slug = loop {
+        let replay = StoredReplay {
+            slug: random_string(SLUG_LENGTH),
+            edit_token_hash: hash_token(&edit_token),
+            uri: definition.uri.clone(),
+            rule: definition.rule.clone(),
+            start: definition.start.clone(),
+            first: definition.first,
+            last: definition.last,
+            title: definition.title.clone(),
+            created: now,
+            updated: now,
+        };
+        match db.create_replay(&replay).await {
+            Ok(()) => break replay.slug,
+            // Unlikely, but there's no harm in trying another.
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => continue,
+            Err(err) => return Err(err.into()),
+        }
+    };
+    let created = Created {
+        path: format!("/r/{slug}"),
+        slug,
+        edit_token,
+    };
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+#[tracing::instrument(skip(db, authorization))]
+pub async fn update(
+    Path(slug): Path<String>,
+    Extension(db): Extension<Db>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    Json(definition): Json<ReplayDefinition>,
+) -> Result<StatusCode, ReplaysError> {
+    definition.validate()?;
+    let stored = db.get_replay(&slug).await?.ok_or(ReplaysError::NotFound)?;
+    let authorized = authorization.map_or(false, |auth| {
+        constant_time_eq(
+            hash_token(auth.0.token()).as_bytes(),
+            stored.edit_token_hash.as_bytes(),
+        )
+    });
+    if !authorized {
+        return Err(ReplaysError::Forbidden);
+    }
+    let replay = StoredReplay {
+        uri: definition.uri,
+        rule: definition.rule,
+        start: definition.start,
+        first: definition.first,
+        last: definition.last,
+        title: definition.title,
+        updated: Utc::now(),
+        ..stored
+    };
+    db.update_replay(&replay).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Serves a stored replay through `replay::replay`, as if everything had been
+/// given in the query string. Anything else that is (`format`, say) is passed
+/// along as well.
+#[tracing::instrument(skip(db, http, request))]
+pub async fn get(
+    Path(slug): Path<String>,
+    Extension(db): Extension<Db>,
+    Extension(http): Extension<HttpClient>,
+    Extension(max_staleness): Extension<MaxStaleness>,
+    Extension(merge_moved_feeds): Extension<MergeMovedFeeds>,
+    Extension(public_base_url): Extension<PublicBaseUrl>,
+    request: Request<Body>,
+) -> Result<Replay, ReplaysError> {
+    let stored = db.get_replay(&slug).await?.ok_or(ReplaysError::NotFound)?;
+    let query = replay_query(&stored, request.uri())?;
+    Ok(replay::replay(
+        query,
+        db,
+        http,
+        max_staleness,
+        merge_moved_feeds,
+        public_base_url,
+        request,
+    )
+    .await?)
+}
+
+fn replay_query(stored: &StoredReplay, uri: &Uri) -> Result<ReplayQuery, ReplaysError> {
+    let format_timestamp = |dt: &DateTime<Utc>| dt.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let mut stored_pairs = vec![
+        ("uri", stored.uri.clone()),
+        ("rule", stored.rule.clone()),
+        ("start", stored.start.clone()),
+    ];
+    if let Some(first) = &stored.first {
+        stored_pairs.push(("first", format_timestamp(first)));
+    }
+    if let Some(last) = &stored.last {
+        stored_pairs.push(("last", format_timestamp(last)));
+    }
+    if let Some(title) = &stored.title {
+        stored_pairs.push(("title", title.clone()));
+    }
+
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    for (key, value) in &stored_pairs {
+        query.append_pair(key, value);
+    }
+    let extra = form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes())
+        .filter(|(key, _)| !stored_pairs.iter().any(|(stored, _)| stored == key));
+    query.extend_pairs(extra);
+
+    let uri: Uri = format!("/?{}", query.finish())
+        .parse()
+        .map_err(|_| ReplaysError::InvalidRequest("Invalid query".to_string()))?;
+    Query::try_from_uri(&uri)
+        .map(|Query(query)| query)
+        .map_err(|err| ReplaysError::InvalidRequest(err.to_string()))
+}
+
+/// Only a hash of each edit token is stored, so the database alone isn't
+/// enough to change anything. The tokens are random enough that there's no
+/// need for a salt or a slow hash.
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Looks at every byte whatever the first difference, so how long it takes
+/// says nothing about how close a guess was.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+#[derive(Error, Debug)]
+pub enum ReplaysError {
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("Replay not found")]
+    NotFound,
+    #[error("Invalid edit token")]
+    Forbidden,
+    #[error("{0}")]
+    Replay(#[from] ReplayError),
+    #[error("Unexpected internal error")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+impl IntoResponse for ReplaysError {
+    fn into_response(self) -> Response<BoxBody> {
+        match self {
+            Self::InvalidRequest(message) => (StatusCode::BAD_REQUEST, message).into_response(),
+            Self::NotFound => StatusCode::NOT_FOUND.into_response(),
+            Self::Forbidden => StatusCode::FORBIDDEN.into_response(),
+            Self::Replay(err) => err.into_response(),
+            Self::DatabaseError(_) => {
+                tracing::error!(?self);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use hyper::{header, HeaderMap, StatusCode};
+use rand::Rng;
+use reqwest::{Request, Response};
+use reqwest_middleware::{Error, Middleware, Next, Result};
+use task_local_extensions::Extensions;
+
+use crate::{fetch::FetchLimits, guard::Blocked, helpers::HeaderMapUtils, limit::RateLimiter};
+
+/// Tries again when an origin seems to be having a momentary problem: the
+/// connection failing or timing out, or a 429 or 5xx response. Each retry
+/// waits twice as long as the one before (or as long as `Retry-After` asks),
+/// and counts against the host's rate limit like any other request.
+pub struct Retry {
+    /// For each attempt to get a response started, so one that never answers
+    /// still leaves time to try again.
+    timeout: Duration,
+    retries: u32,
+    delay: Duration,
+    max_delay: Duration,
+    origins: Arc<RateLimiter<String>>,
+}
+
+impl Retry {
+    pub fn new(limits: &FetchLimits, origins: Arc<RateLimiter<String>>) -> Self {
+        Retry {
+            timeout: limits.read_timeout,
+            retries: limits.retries,
+            delay: limits.retry_delay,
+            max_delay: limits.retry_max_delay,
+            origins,
+        }
+    }
+
+    /// How long to wait before trying again, if it's worth trying at all.
+    fn wait(&self, attempt: u32, result: &Result<Response>) -> Option<Duration> {
+        match result {
+            Ok(resp)
+                if resp.status() == StatusCode::TOO_MANY_REQUESTS
+                    || resp.status().is_server_error() =>
+            {
+                match retry_after(resp.headers()) {
+                    Some(wait) => (wait <= self.max_delay).then_some(wait),
+                    None => Some(self.backoff(attempt)),
+                }
+            }
+            Err(Error::Reqwest(err)) if is_transient(err) => Some(self.backoff(attempt)),
+            Err(Error::Middleware(err)) if err.is::<TimedOut>() => Some(self.backoff(attempt)),
+            _ => None,
+        }
+    }
+
+    /// Somewhere between half and all of the doubled delay, so everything
+    /// that failed at once doesn't all come back at once.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        rand::thread_rng().gen_range(delay / 2..=delay)
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for Retry {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let retry = req.try_clone().filter(|_| attempt < self.retries);
+            let result = tokio::time::timeout(self.timeout, next.clone().run(req, extensions))
+                .await
+                .unwrap_or_else(|_| Err(Error::middleware(TimedOut)));
+            let (Some(retry), Some(wait)) = (retry, self.wait(attempt, &result)) else {
+                return result;
+            };
+            if let Some(host) = retry.url().host_str() {
+                if self.origins.check(host.to_string()).is_err() {
+                    return result;
+                }
+            }
+            tracing::info!(
+                "Retrying {} in {:?} ({})",
+                retry.url(),
+                wait,
+                match &result {
+                    Ok(resp) => resp.status().to_string(),
+                    Err(err) => err.to_string(),
+                }
+            );
+            tokio::time::sleep(wait).await;
+            req = retry;
+            attempt += 1;
+        }
+    }
+}
+
+/// An attempt that didn't get a response in time.
+#[derive(thiserror::Error, Debug)]
+#[error("Timed out waiting for a response")]
+pub struct TimedOut;
+
+/// Connecting or sending failed, for some reason other than us refusing to.
+fn is_transient(err: &reqwest::Error) -> bool {
+    Blocked::find(err).is_none() && (err.is_connect() || err.is_request() || err.is_timeout())
+}
+
+/// Either a number of seconds or a date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let retry_after = headers.get_str(header::RETRY_AFTER)?.trim();
+    if let Ok(seconds) = retry_after.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date: DateTime<Utc> = DateTime::parse_from_rfc2822(retry_after).ok()?.into();
+    Some((date - Utc::now()).to_std().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use hyper::HeaderMap;
+
+    use super::retry_after;
+
+    #[test]
+    fn parses_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert("retry-after", "120".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+        headers.insert(
+            "retry-after",
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+        headers.insert("retry-after", "soon".parse().unwrap());
+        assert_eq!(retry_after(&headers), None);
+    }
+}
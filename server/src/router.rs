@@ -1,16 +1,32 @@
 use crate::config::Config;
 use crate::db::Db;
 use crate::fetch::HttpClient;
+use crate::limit::{limit_clients, ClientLimiter};
 use crate::replay;
+use crate::replays;
 use crate::summary;
 use axum::routing::get_service;
-use axum::{routing::get, Extension, Router};
+use axum::{
+    middleware,
+    routing::{get, post, put},
+    Extension, Router,
+};
 use hyper::StatusCode;
 use tower_http::services::ServeFile;
 use tower_http::{services::ServeDir, trace::TraceLayer};
 
 pub fn make_router(db: Db, http: HttpClient, config: &Config) -> Router {
     Router::new()
+        .route("/summary", get(summary::get))
+        .route("/replay", get(replay::get))
+        .route("/replays", post(replays::create))
+        .route("/replays/:slug", put(replays::update))
+        .route("/r/:slug", get(replays::get))
+        // Only the routes above, leaving static files alone.
+        .route_layer(middleware::from_fn_with_state(
+            ClientLimiter::new(config),
+            limit_clients,
+        ))
         .fallback_service(
             get_service(ServeDir::new(&config.assets_path))
                 .handle_error(|_| async move { StatusCode::NOT_FOUND }),
@@ -23,9 +39,10 @@ pub fn make_router(db: Db, http: HttpClient, config: &Config) -> Router {
             )))
             .handle_error(|_| async move { StatusCode::NOT_FOUND }),
         )
-        .route("/summary", get(summary::get))
-        .route("/replay", get(replay::get))
         .layer(Extension(db))
         .layer(Extension(http))
+        .layer(Extension(replay::MaxStaleness::from(config)))
+        .layer(Extension(replay::MergeMovedFeeds::from(config)))
+        .layer(Extension(replay::PublicBaseUrl::from(config)))
         .layer(TraceLayer::new_for_http())
 }
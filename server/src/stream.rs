@@ -0,0 +1,63 @@
+use std::io::{self, Write};
+
+use axum::body::{Body, Bytes};
+use hyper::body::Sender;
+use tokio::runtime::Handle;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A blocking `Write` that feeds a streaming response body, for producing
+/// output on a blocking thread (e.g. via `spawn_blocking`) without collecting
+/// it all in memory first. Writes are buffered into reasonably sized chunks.
+pub struct BodyWriter {
+    sender: Sender,
+    handle: Handle,
+    buf: Vec<u8>,
+}
+
+impl BodyWriter {
+    /// Must be called from within the runtime, but the writer itself should
+    /// only be used outside of it.
+    pub fn channel() -> (Self, Body) {
+        let (sender, body) = Body::channel();
+        let writer = BodyWriter {
+            sender,
+            handle: Handle::current(),
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        };
+        (writer, body)
+    }
+
+    /// Ends the response with an error so the client can tell it's incomplete
+    /// rather than getting a truncated body that looks like a whole one.
+    pub fn abort(self) {
+        self.sender.abort();
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buf,
+            Vec::with_capacity(CHUNK_SIZE),
+        ));
+        self.handle
+            .block_on(self.sender.send_data(chunk))
+            .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))
+    }
+}
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
@@ -2,6 +2,7 @@ use crate::{
     autodiscovery::{AutodiscoveryException, FeedUrl},
     fetch::{FetchException, HttpClient},
     helpers::HeaderMapUtils,
+    problem::Problem,
 };
 use axum::{
     body::BoxBody,
@@ -19,6 +20,8 @@ use thiserror::Error;
 #[derive(Deserialize)]
 pub struct SummaryQuery {
     uri: String,
+    #[serde(default)]
+    lenient: bool,
 }
 
 pub async fn get(
@@ -36,7 +39,9 @@ pub async fn get(
     let if_none_match = headers.get_string(header::IF_NONE_MATCH);
     tracing::debug!("If-None-Match: {:?}", if_none_match);
 
-    let found = feed_url.attempt_autodiscovery(&http, if_none_match).await?;
+    let found = feed_url
+        .attempt_autodiscovery(&http, if_none_match, query.lenient)
+        .await?;
 
     let mut headers = HeaderMap::new();
     headers.try_append(header::ETAG, found.etag);
@@ -86,6 +91,21 @@ impl IntoResponse for SummaryError {
             Self::Autodiscovery(AutodiscoveryException::Failed) => {
                 (StatusCode::NOT_FOUND, "Unable to find a feed").into_response()
             }
+            Self::Autodiscovery(AutodiscoveryException::Parse(err)) => {
+                tracing::warn!(?err);
+                Problem::feed_parse_error(&err).into_response()
+            }
+            Self::Parse(err) => {
+                tracing::warn!(?err);
+                match err.parse_error() {
+                    Some(parse_error) => Problem::feed_parse_error(parse_error).into_response(),
+                    None => StatusCode::BAD_GATEWAY.into_response(),
+                }
+            }
+            Self::Fetch(err) | Self::Autodiscovery(AutodiscoveryException::Fetch(err)) => {
+                tracing::error!(?err);
+                err.into_response()
+            }
             Self::Unknown | Self::Io(_) => {
                 tracing::error!(?self);
                 StatusCode::INTERNAL_SERVER_ERROR.into_response()
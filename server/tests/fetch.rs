@@ -0,0 +1,211 @@
+mod helpers;
+
+use helpers::{chunked_response, replay_path, Answer, FakeOrigin, TestApp};
+use hyper::StatusCode;
+use podreplay::{config::Config, helpers::HeaderMapUtils};
+use pretty_assertions::assert_eq;
+use std::time::{Duration, Instant};
+
+fn limited_config() -> Config {
+    Config {
+        fetch_allow: vec!["127.0.0.1".to_string()],
+        fetch_max_body_size: 1024,
+        fetch_read_timeout: 1,
+        ..Config::default()
+    }
+}
+
+async fn problem_detail(response: reqwest::Response) -> String {
+    let body = response.bytes().await.unwrap();
+    let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    problem["detail"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn reads_feeds_within_the_limits() {
+    let xml = include_bytes!("../../lib/tests/data/sample_rss_2.0.xml");
+    let origin = FakeOrigin::always(Answer::Raw(chunked_response(xml))).await;
+
+    let app = TestApp::new().await;
+    let response = app.get(&replay_path(&origin.uri)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn refuses_feeds_declared_too_large() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").with_body(xml).create();
+
+    let app = TestApp::with_config(limited_config()).await;
+    let path = replay_path(&format!("{}/hello", server.url()));
+    let response = app.get(&path).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(
+        response.headers().get_str("content-type"),
+        Some("application/problem+json")
+    );
+    assert_eq!(
+        problem_detail(response).await,
+        "Feed is larger than 1024 bytes"
+    );
+    mock.assert();
+}
+
+#[tokio::test]
+async fn stops_reading_feeds_that_turn_out_too_large() {
+    let response = chunked_response(&[b' '; 2048]);
+    let origin = FakeOrigin::always(Answer::Raw(response)).await;
+
+    let app = TestApp::with_config(limited_config()).await;
+    let response = app.get(&replay_path(&origin.uri)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(
+        problem_detail(response).await,
+        "Feed is larger than 1024 bytes"
+    );
+}
+
+#[tokio::test]
+async fn gives_up_on_origins_that_never_respond() {
+    let origin = FakeOrigin::always(Answer::Hang).await;
+
+    let app = TestApp::with_config(limited_config()).await;
+    let response = app.get(&replay_path(&origin.uri)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(problem_detail(response).await, "Timed out fetching feed");
+}
+
+#[tokio::test]
+async fn gives_up_on_origins_that_never_finish() {
+    // Always just quick enough to not hit the read timeout.
+    let origin = FakeOrigin::always(Answer::Trickle {
+        head: b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec(),
+        each: b"1\r\n \r\n".to_vec(),
+        every: Duration::from_millis(500),
+    })
+    .await;
+
+    let app = TestApp::with_config(Config {
+        fetch_total_timeout: 2,
+        ..limited_config()
+    })
+    .await;
+    let started = Instant::now();
+    let response = app.get(&replay_path(&origin.uri)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn reuses_recent_fetches() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("GET", "/hello")
+        .with_header("ETag", r#""v1""#)
+        .with_body(xml)
+        .expect(1)
+        .create();
+
+    let app = TestApp::new().await;
+    let path = replay_path(&format!("{}/hello", server.url()));
+    for _ in 0..3 {
+        let response = app.get(&path).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    mock.assert();
+}
+
+#[tokio::test]
+async fn fetches_again_if_told_not_to_reuse() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("GET", "/hello")
+        .with_header("Cache-Control", "no-store")
+        .with_body(xml)
+        .expect(2)
+        .create();
+
+    let app = TestApp::new().await;
+    let path = replay_path(&format!("{}/hello", server.url()));
+    for _ in 0..2 {
+        let response = app.get(&path).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    mock.assert();
+}
+
+#[tokio::test]
+async fn shares_fetches_already_under_way() {
+    let xml = include_bytes!("../../lib/tests/data/sample_rss_2.0.xml");
+    let response = chunked_response(xml);
+    let origin = FakeOrigin::new(Duration::from_millis(200), move |_| {
+        Answer::Raw(response.clone())
+    })
+    .await;
+
+    let app = TestApp::new().await;
+    let path = replay_path(&origin.uri);
+    let (a, b, c) = tokio::join!(
+        app.get(&path).send(),
+        app.get(&path).send(),
+        app.get(&path).send()
+    );
+
+    for response in [a, b, c] {
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+    }
+    assert_eq!(origin.requests(), 1);
+}
+
+#[tokio::test]
+async fn shares_failures_with_everyone_waiting() {
+    let origin = FakeOrigin::always(Answer::Hang).await;
+
+    let app = TestApp::with_config(Config {
+        fetch_retries: 0,
+        ..limited_config()
+    })
+    .await;
+    let path = replay_path(&origin.uri);
+    let started = Instant::now();
+    let (a, b, c) = tokio::join!(
+        app.get(&path).send(),
+        app.get(&path).send(),
+        app.get(&path).send()
+    );
+
+    for response in [a, b, c] {
+        assert_eq!(response.unwrap().status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!(origin.requests(), 1);
+}
+
+#[tokio::test]
+async fn only_shares_feeds_once_they_have_been_read() {
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("GET", "/hello")
+        .with_body("<rss><channel><title>Oops</rss>")
+        .expect(2)
+        .create();
+
+    let app = TestApp::new().await;
+    let path = replay_path(&format!("{}/hello", server.url()));
+    for _ in 0..2 {
+        let response = app.get(&path).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    mock.assert();
+}
@@ -0,0 +1,80 @@
+mod helpers;
+
+use std::time::Duration;
+
+use helpers::{replay_path, TestApp};
+use hyper::StatusCode;
+use podreplay::{
+    config::Config,
+    fetch::{FetchLimits, HttpClient},
+    guard::AddressGuard,
+};
+use pretty_assertions::assert_eq;
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn refuses_loopback_addresses() {
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").expect(0).create();
+
+    let app = TestApp::with_config(Config::default()).await;
+    let path = replay_path(&format!("{}/hello", server.url()));
+    let response = app.get(&path).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    mock.assert();
+}
+
+#[tokio::test]
+async fn refuses_names_that_resolve_to_loopback_addresses() {
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").expect(0).create();
+
+    let app = TestApp::with_config(Config::default()).await;
+    let path = replay_path(&server.url().replace("127.0.0.1", "localhost"));
+    let response = app.get(&path).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    mock.assert();
+}
+
+#[tokio::test]
+async fn refuses_redirects_to_disallowed_addresses() {
+    let internal = TcpListener::bind("[::1]:0").await.unwrap();
+    let internal_uri = format!("http://{}/secret", internal.local_addr().unwrap());
+
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("GET", "/hello")
+        .with_status(302)
+        .with_header("Location", &internal_uri)
+        .create();
+
+    // Only the mock origin itself is allowed.
+    let app = TestApp::new().await;
+    let path = replay_path(&format!("{}/hello", server.url()));
+    let response = app.get(&path).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    mock.assert();
+    let connected = tokio::time::timeout(Duration::from_millis(100), internal.accept()).await;
+    assert!(connected.is_err(), "the redirect was followed");
+}
+
+#[tokio::test]
+async fn blocked_names_are_not_mistaken_for_unavailable_origins() {
+    let config = Config::default();
+    let client = HttpClient::new(
+        config.user_agent.clone(),
+        AddressGuard::from_config(&config).unwrap(),
+        FetchLimits::from(&config),
+    );
+    let err = client
+        .get_spooled("http://localhost:1/feed", None, None)
+        .await
+        .err()
+        .unwrap();
+
+    assert_eq!(err.to_string(), "Refusing to fetch from localhost");
+    assert!(!err.is_unavailable());
+}
@@ -1,6 +1,25 @@
-use podreplay::{config::Config, db::Db, fetch::HttpClient, router::make_router};
-use std::net::{SocketAddr, TcpListener};
-use tokio::task::JoinHandle;
+// Not every test uses everything in here.
+#![allow(dead_code)]
+
+use podreplay::{
+    config::Config,
+    db::Db,
+    fetch::{FetchLimits, HttpClient},
+    guard::AddressGuard,
+    router::make_router,
+};
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    task::JoinHandle,
+};
 use url::Url;
 
 pub struct TestApp {
@@ -11,11 +30,23 @@ pub struct TestApp {
 
 impl TestApp {
     pub async fn new() -> TestApp {
-        let config = Config::default();
+        // Everything is served from a local mock origin.
+        TestApp::with_config(Config {
+            fetch_allow: vec!["127.0.0.1".to_string()],
+            ..Config::default()
+        })
+        .await
+    }
+
+    pub async fn with_config(config: Config) -> TestApp {
         let db = Db::new("sqlite::memory:".to_string()).await.unwrap();
         db.migrate().await.unwrap();
 
-        let http = HttpClient::new(config.user_agent.clone());
+        let http = HttpClient::new(
+            config.user_agent.clone(),
+            AddressGuard::from_config(&config).unwrap(),
+            FetchLimits::from(&config),
+        );
         let app = make_router(db, http, &config);
 
         let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind ephemeral socket");
@@ -49,3 +80,105 @@ impl Drop for TestApp {
         self.server.abort()
     }
 }
+
+pub fn replay_path(uri: &str) -> String {
+    format!("/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-10-25T01:09:00Z&uri={uri}")
+}
+
+/// How a `FakeOrigin` answers a request.
+#[derive(Clone, Debug)]
+pub enum Answer {
+    /// Sends back exactly these bytes.
+    Raw(Vec<u8>),
+    /// Hangs up without answering.
+    HangUp,
+    /// Never answers at all.
+    Hang,
+    /// Sends `head`, then `each` again and again, `every` so often.
+    Trickle {
+        head: Vec<u8>,
+        each: Vec<u8>,
+        every: Duration,
+    },
+}
+
+/// An origin that answers however it's told to, for anything mockito can't
+/// do. Counts how many requests it gets.
+pub struct FakeOrigin {
+    pub uri: String,
+    requests: Arc<AtomicUsize>,
+}
+
+impl FakeOrigin {
+    /// Answers every request the same way, straight away.
+    pub async fn always(answer: Answer) -> FakeOrigin {
+        FakeOrigin::new(Duration::ZERO, move |_| answer.clone()).await
+    }
+
+    /// Answers the `n`th request (counting from 0) with `answer(n)`, after
+    /// waiting for `delay`.
+    pub async fn new(
+        delay: Duration,
+        answer: impl Fn(usize) -> Answer + Send + Sync + 'static,
+    ) -> FakeOrigin {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let answer = Arc::new(answer);
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let counter = counter.clone();
+                let answer = answer.clone();
+                tokio::spawn(async move {
+                    let mut request = [0; 1024];
+                    let _ = socket.read(&mut request).await;
+                    let n = counter.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(delay).await;
+                    match answer(n) {
+                        Answer::Raw(response) => {
+                            let _ = socket.write_all(&response).await;
+                        }
+                        Answer::HangUp => {}
+                        Answer::Hang => std::future::pending().await,
+                        Answer::Trickle { head, each, every } => {
+                            let _ = socket.write_all(&head).await;
+                            while socket.write_all(&each).await.is_ok() {
+                                tokio::time::sleep(every).await;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        FakeOrigin {
+            uri: format!("http://{addr}/feed"),
+            requests,
+        }
+    }
+
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+/// A whole response, with the connection closed after it.
+pub fn response(status: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 {status}\r\nConnection: close\r\nContent-Length: {}\r\n{headers}\r\n",
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+/// Sends `body` in a single chunk, so there's no length up front.
+pub fn chunked_response(body: &[u8]) -> Vec<u8> {
+    let mut response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    response.extend_from_slice(format!("{:x}\r\n", body.len()).as_bytes());
+    response.extend_from_slice(body);
+    response.extend_from_slice(b"\r\n0\r\n\r\n");
+    response
+}
@@ -0,0 +1,109 @@
+mod helpers;
+
+use helpers::TestApp;
+use hyper::{header, StatusCode};
+use podreplay::config::Config;
+use pretty_assertions::assert_eq;
+
+async fn statuses(app: &TestApp, client_ip: Option<&str>, count: usize) -> Vec<StatusCode> {
+    let mut statuses = Vec::new();
+    for _ in 0..count {
+        let request = app.get("/r/nothing");
+        let request = match client_ip {
+            Some(ip) => request.header("Fly-Client-IP", ip),
+            None => request,
+        };
+        statuses.push(request.send().await.unwrap().status());
+    }
+    statuses
+}
+
+#[tokio::test]
+async fn allows_regular_polling() {
+    let app = TestApp::new().await;
+    assert!(statuses(&app, None, 10)
+        .await
+        .iter()
+        .all(|status| *status == StatusCode::NOT_FOUND));
+}
+
+#[tokio::test]
+async fn limits_each_client() {
+    let app = TestApp::with_config(Config {
+        client_requests_per_minute: 2,
+        ..Config::default()
+    })
+    .await;
+
+    assert_eq!(
+        statuses(&app, None, 2).await,
+        vec![StatusCode::NOT_FOUND, StatusCode::NOT_FOUND]
+    );
+    let response = app.get("/r/nothing").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "30");
+
+    // Static files aren't limited.
+    let response = app.get("/").send().await.unwrap();
+    assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn tells_clients_apart_by_trusted_header() {
+    let app = TestApp::with_config(Config {
+        client_requests_per_minute: 1,
+        client_ip_header: Some("Fly-Client-IP".to_string()),
+        ..Config::default()
+    })
+    .await;
+
+    assert_eq!(
+        statuses(&app, Some("203.0.113.1"), 2).await,
+        vec![StatusCode::NOT_FOUND, StatusCode::TOO_MANY_REQUESTS]
+    );
+    assert_eq!(
+        statuses(&app, Some("203.0.113.2"), 1).await,
+        vec![StatusCode::NOT_FOUND]
+    );
+}
+
+#[tokio::test]
+async fn limits_requests_to_each_origin() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("GET", "/hello")
+        .with_header("Cache-Control", "no-cache")
+        .with_body(xml)
+        .create();
+    let other = server.mock("GET", "/other").expect(0).create();
+
+    let app = TestApp::with_config(Config {
+        fetch_allow: vec!["127.0.0.1".to_string()],
+        origin_requests_per_minute: 1,
+        ..Config::default()
+    })
+    .await;
+    let path = |feed: &str| {
+        format!(
+            "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-10-25T01:09:00Z&uri={}/{feed}",
+            server.url()
+        )
+    };
+
+    let response = app.get(&path("hello")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // What we already have can still be replayed...
+    let response = app.get(&path("hello")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("warning").is_some());
+
+    // ...but nothing new is fetched from the same host.
+    let response = app.get(&path("other")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().get(header::RETRY_AFTER).is_some());
+
+    mock.assert();
+    other.assert();
+}
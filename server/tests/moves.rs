@@ -0,0 +1,317 @@
+mod helpers;
+
+use chrono::{DateTime, TimeZone, Utc};
+use helpers::{replay_path, TestApp};
+use hyper::StatusCode;
+use podreplay::{config::Config, db::Db};
+use podreplay_lib::CachedEntry;
+use pretty_assertions::assert_eq;
+
+/// Replays `/old` twice, checking that both work.
+async fn replay_old_twice(server: &mockito::Server) {
+    let app = TestApp::new().await;
+    let path = replay_path(&format!("{}/old", server.url()));
+    for _ in 0..2 {
+        let response = app.get(&path).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn follows_permanent_redirects() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let old = server
+        .mock("GET", "/old")
+        .with_status(301)
+        .with_header("Location", &format!("{}/new", server.url()))
+        .expect(1)
+        .create();
+    let new = server
+        .mock("GET", "/new")
+        .with_header("Cache-Control", "no-cache")
+        .with_body(xml)
+        .expect(2)
+        .create();
+
+    replay_old_twice(&server).await;
+
+    old.assert();
+    new.assert();
+}
+
+#[tokio::test]
+async fn keeps_fetching_through_temporary_redirects() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let old = server
+        .mock("GET", "/old")
+        .with_status(302)
+        .with_header("Location", &format!("{}/new", server.url()))
+        .expect(2)
+        .create();
+    let new = server
+        .mock("GET", "/new")
+        .with_header("Cache-Control", "no-cache")
+        .with_body(xml)
+        .expect(2)
+        .create();
+
+    replay_old_twice(&server).await;
+
+    old.assert();
+    new.assert();
+}
+
+#[tokio::test]
+async fn follows_new_feed_url() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let moved = xml.replace(
+        "<channel>",
+        &format!(
+            "<channel><itunes:new-feed-url>{}/new</itunes:new-feed-url>",
+            server.url()
+        ),
+    );
+    let old = server
+        .mock("GET", "/old")
+        .with_header("Cache-Control", "no-cache")
+        .with_body(moved)
+        .expect(1)
+        .create();
+    // Once to check there's a feed there, then for the second replay.
+    let new = server
+        .mock("GET", "/new")
+        .with_header("Cache-Control", "no-cache")
+        .with_body(xml)
+        .expect(2)
+        .create();
+
+    replay_old_twice(&server).await;
+
+    old.assert();
+    new.assert();
+}
+
+/// Serves `/old` saying it's moved to `/new`, which is only checked the first
+/// time since `/old` hasn't changed after that.
+async fn declared_move(
+    server: &mut mockito::Server,
+    new: mockito::Mock,
+) -> (mockito::Mock, mockito::Mock) {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let moved = xml.replace(
+        "<channel>",
+        &format!(
+            "<channel><itunes:new-feed-url>{}/new</itunes:new-feed-url>",
+            server.url()
+        ),
+    );
+    let old = server
+        .mock("GET", "/old")
+        .with_header("Cache-Control", "no-cache")
+        .with_body(moved)
+        .expect(2)
+        .create();
+    (old, new.expect(1).create())
+}
+
+#[tokio::test]
+async fn ignores_new_feed_url_without_a_feed_there() {
+    let mut server = mockito::Server::new();
+    let new = server.mock("GET", "/new").with_status(404);
+    let (old, new) = declared_move(&mut server, new).await;
+
+    replay_old_twice(&server).await;
+
+    old.assert();
+    new.assert();
+}
+
+#[tokio::test]
+async fn ignores_new_feed_url_pointing_back() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let back = xml.replace(
+        "<channel>",
+        &format!(
+            "<channel><itunes:new-feed-url>{}/old</itunes:new-feed-url>",
+            server.url()
+        ),
+    );
+    let new = server.mock("GET", "/new").with_body(back);
+    let (old, new) = declared_move(&mut server, new).await;
+
+    replay_old_twice(&server).await;
+
+    old.assert();
+    new.assert();
+}
+
+#[tokio::test]
+async fn never_merges_declared_moves() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    // Replayed once, then checked for each replay of `/old`.
+    let new = server
+        .mock("GET", "/new")
+        .with_header("Cache-Control", "no-cache")
+        .with_body(xml)
+        .expect(3)
+        .create();
+    let old = server
+        .mock("GET", "/old")
+        .with_header("Cache-Control", "no-cache")
+        .with_body(xml.replace(
+            "<channel>",
+            &format!(
+                "<channel><itunes:new-feed-url>{}/new</itunes:new-feed-url>",
+                server.url()
+            ),
+        ))
+        .expect(2)
+        .create();
+
+    let app = TestApp::with_config(Config {
+        fetch_allow: vec!["127.0.0.1".to_string()],
+        merge_moved_feeds: true,
+        ..Config::default()
+    })
+    .await;
+    // `/new` already has a history of its own.
+    let response = app
+        .get(&replay_path(&format!("{}/new", server.url())))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let path = replay_path(&format!("{}/old", server.url()));
+    for _ in 0..2 {
+        let response = app.get(&path).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    old.assert();
+    new.assert();
+}
+
+fn entry(id: &str, feed_id: i64, noticed: DateTime<Utc>) -> CachedEntry {
+    CachedEntry {
+        id: id.to_string(),
+        feed_id,
+        noticed,
+        published: None,
+    }
+}
+
+#[tokio::test]
+async fn merges_moved_feeds_only_when_asked() {
+    let db = Db::new("sqlite::memory:".to_string()).await.unwrap();
+    db.migrate().await.unwrap();
+    let earlier = Utc.with_ymd_and_hms(2021, 10, 1, 0, 0, 0).unwrap();
+    let later = Utc.with_ymd_and_hms(2021, 10, 2, 0, 0, 0).unwrap();
+
+    let old = db
+        .update_feed_meta("https://example.com/old", &earlier, &None, &None)
+        .await
+        .unwrap();
+    db.update_cached_entries(
+        old.id,
+        &[entry("a", old.id, earlier), entry("b", old.id, later)],
+    )
+    .await
+    .unwrap();
+    let new = db
+        .update_feed_meta("https://example.com/new", &later, &None, &None)
+        .await
+        .unwrap();
+    db.update_cached_entries(
+        new.id,
+        &[entry("a", new.id, later), entry("b", new.id, earlier)],
+    )
+    .await
+    .unwrap();
+
+    let moved = db
+        .move_feed(
+            "https://example.com/old",
+            "https://example.com/new",
+            &later,
+            false,
+        )
+        .await
+        .unwrap();
+    assert!(!moved);
+    assert_eq!(
+        db.resolve_feed_uri("https://example.com/old")
+            .await
+            .unwrap(),
+        "https://example.com/old"
+    );
+
+    let moved = db
+        .move_feed(
+            "https://example.com/old",
+            "https://example.com/new",
+            &later,
+            true,
+        )
+        .await
+        .unwrap();
+    assert!(moved);
+    assert_eq!(
+        db.resolve_feed_uri("https://example.com/old")
+            .await
+            .unwrap(),
+        "https://example.com/new"
+    );
+    assert!(db
+        .get_feed("https://example.com/old")
+        .await
+        .unwrap()
+        .is_none());
+    // Whichever noticed each one first wins.
+    assert_eq!(
+        db.get_entries(new.id).await.unwrap(),
+        vec![entry("a", new.id, earlier), entry("b", new.id, earlier)]
+    );
+}
+
+#[tokio::test]
+async fn moves_feeds_to_new_addresses_and_back() {
+    let db = Db::new("sqlite::memory:".to_string()).await.unwrap();
+    db.migrate().await.unwrap();
+    let now = Utc::now();
+    let feed = db
+        .update_feed_meta("https://example.com/a", &now, &None, &None)
+        .await
+        .unwrap();
+
+    for (from, to) in [
+        ("https://example.com/a", "https://example.com/b"),
+        ("https://example.com/b", "https://example.com/a"),
+        ("https://example.com/a", "https://example.com/c"),
+    ] {
+        assert!(db.move_feed(from, to, &now, false).await.unwrap());
+    }
+
+    for uri in [
+        "https://example.com/a",
+        "https://example.com/b",
+        "https://example.com/c",
+    ] {
+        assert_eq!(
+            db.resolve_feed_uri(uri).await.unwrap(),
+            "https://example.com/c"
+        );
+    }
+    assert_eq!(
+        db.get_feed("https://example.com/c")
+            .await
+            .unwrap()
+            .unwrap()
+            .id,
+        feed.id
+    );
+}
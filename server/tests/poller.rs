@@ -0,0 +1,110 @@
+use chrono::{TimeZone, Utc};
+use mockito::Matcher;
+use podreplay::{
+    config::Config,
+    db::Db,
+    fetch::{FetchLimits, HttpClient},
+    guard::AddressGuard,
+    poller::Poller,
+};
+use pretty_assertions::assert_eq;
+
+#[tokio::test]
+async fn polls_known_feeds() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let fresh = server
+        .mock("GET", "/hello")
+        .match_header("If-None-Match", Matcher::Missing)
+        .with_header("etag", r#""v1""#)
+        .with_header("cache-control", "no-cache")
+        .with_body(xml)
+        .create();
+    let unchanged = server
+        .mock("GET", "/hello")
+        .match_header("If-None-Match", r#""v1""#)
+        .with_status(304)
+        .create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let config = Config {
+        poll_jitter: 0,
+        fetch_allow: vec!["127.0.0.1".to_string()],
+        ..Config::default()
+    };
+    let db = Db::new("sqlite::memory:".to_string()).await.unwrap();
+    db.migrate().await.unwrap();
+    let http = HttpClient::new(
+        config.user_agent.clone(),
+        AddressGuard::from_config(&config).unwrap(),
+        FetchLimits::from(&config),
+    );
+    let poller = Poller::new(db.clone(), http, &config);
+
+    // Known from some earlier replay, but nothing has been seen in it yet.
+    let first_fetched = Utc.with_ymd_and_hms(2021, 10, 1, 0, 0, 0).unwrap();
+    let feed = db
+        .update_feed_meta(&mock_uri, &first_fetched, &None, &None)
+        .await
+        .unwrap();
+
+    let before = Utc::now();
+    poller.poll_all().await.unwrap();
+    fresh.assert();
+
+    let entries = db.get_entries(feed.id).await.unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(entries.iter().all(|entry| entry.noticed >= before));
+    let feed_meta = db.get_feeds().await.unwrap().remove(0);
+    assert_eq!(feed_meta.etag.as_deref(), Some(r#""v1""#));
+    assert_eq!(feed_meta.first_fetched, first_fetched);
+
+    // The stored etag is sent along the next time around.
+    poller.poll_all().await.unwrap();
+    unchanged.assert();
+    assert_eq!(db.get_entries(feed.id).await.unwrap(), entries);
+}
+
+#[tokio::test]
+async fn waits_for_origins_to_have_requests_to_spare() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("GET", "/hello")
+        .with_body(xml)
+        .expect(31)
+        .create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let config = Config {
+        poll_jitter: 0,
+        origin_requests_per_minute: 60,
+        fetch_allow: vec!["127.0.0.1".to_string()],
+        ..Config::default()
+    };
+    let db = Db::new("sqlite::memory:".to_string()).await.unwrap();
+    db.migrate().await.unwrap();
+    let http = HttpClient::new(
+        config.user_agent.clone(),
+        AddressGuard::from_config(&config).unwrap(),
+        FetchLimits::from(&config),
+    );
+    let poller = Poller::new(db.clone(), http.clone(), &config);
+    let first_fetched = Utc.with_ymd_and_hms(2021, 10, 1, 0, 0, 0).unwrap();
+    let feed = db
+        .update_feed_meta(&mock_uri, &first_fetched, &None, &None)
+        .await
+        .unwrap();
+
+    // Half the bucket is kept for everyone else, so this leaves nothing for
+    // the poller until a little more has built up.
+    for _ in 0..30 {
+        http.get(&mock_uri, None, None).await.unwrap();
+    }
+
+    let started = std::time::Instant::now();
+    poller.poll_all().await.unwrap();
+    assert!(started.elapsed() >= std::time::Duration::from_millis(500));
+    mock.assert();
+    assert_eq!(db.get_entries(feed.id).await.unwrap().len(), 2);
+}
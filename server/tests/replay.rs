@@ -3,10 +3,19 @@ mod helpers;
 use axum::body::Body;
 use helpers::TestApp;
 use hyper::{header, StatusCode};
-use podreplay::helpers::HeaderMapUtils;
+use podreplay::{config::Config, helpers::HeaderMapUtils};
 use pretty_assertions::assert_eq;
 use tracing_test::traced_test;
 
+/// A strict, namespace aware parser will refuse anything with an undeclared
+/// prefix, as plenty of podcast apps do.
+fn assert_namespace_well_formed(body: &[u8]) {
+    let body = std::str::from_utf8(body).unwrap();
+    if let Err(err) = roxmltree::Document::parse(body) {
+        panic!("Output is not namespace well-formed: {err}");
+    }
+}
+
 #[traced_test]
 #[tokio::test]
 async fn returns_200_for_atom() {
@@ -28,19 +37,20 @@ async fn returns_200_for_atom() {
     let expected = xml
         .replace(
             "<feed xmlns=\"http://www.w3.org/2005/Atom\">",
-            "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n    <itunes:block>Yes</itunes:block>",
+            "<feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\" xmlns:podcast=\"https://podcastindex.org/namespace/1.0\">\n    <itunes:block>Yes</itunes:block>\n    <podcast:locked>yes</podcast:locked>",
         )
         .replace(
             "<title>Example Feed</title>",
             "<title>My Custom Title</title>",
         )
         .replace(
-            "        <updated>2003-12-13T18:30:02Z</updated>",
-            "        <updated>2021-10-23T01:09:00Z</updated>",
+            "<updated>2003-12-13T18:30:02Z</updated>",
+            "<updated>2021-10-23T01:09:00Z</updated>",
         );
     assert_eq!(expected, body);
     assert_eq!(status, StatusCode::OK);
     assert_eq!(content_type, "application/rss+xml");
+    assert_namespace_well_formed(&body);
 
     mock.assert();
 }
@@ -71,9 +81,18 @@ async fn returns_200_for_rss() {
             "\n\t\t\t<pubDate>Sun, 29 Sep 2002 19:59:01 GMT</pubDate>",
             "\n\t\t\t<pubDate>Sat, 23 Oct 2021 01:09:00 +0000</pubDate>",
         )
+        .replace(
+            "<lastBuildDate>Mon, 30 Sep 2002 11:00:00 GMT</lastBuildDate>",
+            "<lastBuildDate>Sat, 30 Oct 2021 01:09:00 +0000</lastBuildDate>",
+        )
+        .replace("<ttl>40</ttl>", "")
+        .replace(
+            "xmlns:blogChannel=\"http://backend.userland.com/blogChannelModule\">",
+            "xmlns:blogChannel=\"http://backend.userland.com/blogChannelModule\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\" xmlns:podcast=\"https://podcastindex.org/namespace/1.0\">",
+        )
         .replace(
             "<channel>",
-            "<channel>\n        <itunes:block>Yes</itunes:block>",
+            "<channel>\n        <itunes:block>Yes</itunes:block>\n        <podcast:locked>yes</podcast:locked>",
         )
         .replace(
             "<title>Scripting News</title>",
@@ -82,6 +101,7 @@ async fn returns_200_for_rss() {
     assert_eq!(expected, body);
     assert_eq!(status, StatusCode::OK);
     assert_eq!(content_type, "application/rss+xml");
+    assert_namespace_well_formed(&body);
 
     mock.assert();
 }
@@ -158,3 +178,545 @@ async fn returns_304_if_feed_returns_304() {
 
     mock.assert();
 }
+
+/// Replays the same feed twice, with the origin answering the second request
+/// (which should send along the etag from the first) however it likes. The
+/// first response can't be reused, so the second request does get made.
+async fn replay_twice(
+    app: &TestApp,
+    second: impl FnOnce(mockito::Mock) -> mockito::Mock,
+) -> reqwest::Response {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let first = server
+        .mock("GET", "/hello")
+        .match_header("If-None-Match", mockito::Matcher::Missing)
+        .with_header("ETag", r#""v1""#)
+        .with_header("Cache-Control", "no-cache")
+        .with_body(xml)
+        .create();
+    let second = second(
+        server
+            .mock("GET", "/hello")
+            .match_header("If-None-Match", r#""v1""#),
+    )
+    .create();
+    let mock_uri = format!("{}/hello", &server.url());
+    let path = format!(
+        "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-10-25T01:09:00Z&uri={mock_uri}"
+    );
+
+    let response = app.get(&path).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.get(&path).send().await.unwrap();
+
+    first.assert();
+    second.assert();
+    response
+}
+
+#[traced_test]
+#[tokio::test]
+async fn replays_stored_copy_if_feed_returns_304() {
+    let app = TestApp::new().await;
+    let response = replay_twice(&app, |mock| mock.with_status(304)).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("warning").is_none());
+    let body = response.text().await.unwrap();
+    assert!(body.contains("<pubDate>Sat, 23 Oct 2021 01:09:00 +0000</pubDate>"));
+}
+
+#[traced_test]
+#[tokio::test]
+async fn replays_stale_copy_if_feed_is_down() {
+    let app = TestApp::new().await;
+    let response = replay_twice(&app, |mock| mock.with_status(503).expect_at_least(1)).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("warning").unwrap(),
+        r#"110 - "Response is Stale""#
+    );
+    let body = response.text().await.unwrap();
+    assert!(body.contains("<pubDate>Sat, 23 Oct 2021 01:09:00 +0000</pubDate>"));
+}
+
+#[traced_test]
+#[tokio::test]
+async fn returns_502_if_feed_is_down_and_stored_copy_is_too_old() {
+    let app = TestApp::with_config(Config {
+        max_staleness: 0,
+        fetch_allow: vec!["127.0.0.1".to_string()],
+        ..Config::default()
+    })
+    .await;
+    let response = replay_twice(&app, |mock| mock.with_status(503).expect_at_least(1)).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[traced_test]
+#[tokio::test]
+async fn counts_stored_copy_as_fresh_after_a_304() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let first = server
+        .mock("GET", "/hello")
+        .match_header("If-None-Match", mockito::Matcher::Missing)
+        .with_header("ETag", r#""v1""#)
+        .with_header("Cache-Control", "no-cache")
+        .with_body(xml)
+        .create();
+    let unchanged = server
+        .mock("GET", "/hello")
+        .match_header("If-None-Match", r#""v1""#)
+        .with_header("Cache-Control", "no-cache")
+        .with_status(304)
+        .create();
+    let mock_uri = format!("{}/hello", &server.url());
+    let path = format!(
+        "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-10-25T01:09:00Z&uri={mock_uri}"
+    );
+
+    let app = TestApp::with_config(Config {
+        max_staleness: 1,
+        fetch_allow: vec!["127.0.0.1".to_string()],
+        ..Config::default()
+    })
+    .await;
+    let response = app.get(&path).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // Longer ago than we'd serve a stale copy from, but the origin then
+    // confirms it's still current.
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    let response = app.get(&path).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    first.assert();
+    unchanged.assert();
+
+    unchanged.remove();
+    let down = server
+        .mock("GET", "/hello")
+        .with_status(503)
+        .expect_at_least(1)
+        .create();
+    let response = app.get(&path).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("warning").is_some());
+    down.assert();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn honors_last_modified() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let feed_modified = "Wed, 20 Oct 2021 00:00:00 GMT";
+    let mut server = mockito::Server::new();
+    let fresh = server
+        .mock("GET", "/hello")
+        .match_header("If-Modified-Since", mockito::Matcher::Missing)
+        .with_header("Last-Modified", feed_modified)
+        .with_header("Cache-Control", "no-cache")
+        .with_body(xml)
+        .create();
+    let unchanged = server
+        .mock("GET", "/hello")
+        .match_header("If-Modified-Since", feed_modified)
+        .with_header("Cache-Control", "no-cache")
+        .with_status(304)
+        .expect(2)
+        .create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let app = TestApp::new().await;
+    let path = format!(
+        "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-10-25T01:09:00Z&uri={mock_uri}"
+    );
+
+    // The first item went out after the feed last changed.
+    let replay_modified = "Sat, 23 Oct 2021 01:09:00 GMT";
+    for _ in 0..2 {
+        let response = app.get(&path).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::LAST_MODIFIED).unwrap(),
+            replay_modified
+        );
+    }
+
+    let response = app
+        .get(&path)
+        .header("If-Modified-Since", replay_modified)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(
+        response.headers().get(header::EXPIRES).unwrap(),
+        "Sat, 30 Oct 2021 01:09:00 +0000"
+    );
+
+    fresh.assert();
+    unchanged.assert();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn returns_problem_details_for_broken_feeds() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml").replace(
+        "Who loves namespaces?&lt;/a&gt;</description>",
+        "Who loves namespaces?&lt;/a&gt;</descriptio>",
+    );
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/broken").with_body(xml).create();
+    let mock_uri = format!("{}/broken", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!(
+        "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-11-23T01:09:00Z&uri={mock_uri}"
+    );
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+    let content_type = response.headers().get_string("content-type").unwrap();
+    let body = response.bytes().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(content_type, "application/problem+json");
+    assert_eq!(body["status"], 502);
+    assert_eq!(body["line"], 32);
+    assert_eq!(body["column"], 143);
+    assert_eq!(body["path"], "rss/channel/item[2]/description");
+    assert_eq!(
+        body["snippet"],
+        "ot;&gt;Who loves namespaces?&lt;/a&gt;</descriptio>"
+    );
+
+    mock.assert();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn skips_broken_items_when_lenient() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml").replace(
+        "Who loves namespaces?&lt;/a&gt;</description>",
+        "Who loves namespaces?&lt;/a&gt;</descriptio>",
+    );
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/broken").with_body(xml).create();
+    let mock_uri = format!("{}/broken", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!(
+        "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-11-23T01:09:00Z&lenient=true&uri={mock_uri}"
+    );
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+    let body = response.text().await.unwrap();
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("When:6:56:02PM"));
+    assert!(!body.contains("When:12:59:01PM"));
+
+    mock.assert();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn points_self_links_at_the_replay() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml").replace(
+        "<channel>",
+        r#"<channel>
+        <atom:link href="https://example.com/original" rel="self" type="application/rss+xml" />
+        <itunes:new-feed-url>https://example.com/moved</itunes:new-feed-url>"#,
+    );
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").with_body(xml).create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!(
+        "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-11-23T01:09:00Z&uri={mock_uri}"
+    );
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+    let body = response.text().await.unwrap();
+
+    let mut self_url = app.base_url.join("/replay").unwrap();
+    self_url
+        .query_pairs_mut()
+        .append_pair("rule", "1w")
+        .append_pair("start", "2021-10-23T01:09:00Z")
+        .append_pair("uri", &mock_uri);
+    let self_link = format!(
+        r#"<atom:link href="{}" rel="self" type="application/rss+xml"/>"#,
+        self_url.as_str().replace('&', "&amp;")
+    );
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains(&self_link), "{body}");
+    assert!(!body.contains("example.com/original"));
+    assert!(!body.contains("new-feed-url"));
+
+    mock.assert();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn points_self_links_at_the_public_base_url() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml").replace(
+        "<channel>",
+        r#"<channel>
+        <atom:link href="https://example.com/original" rel="self" type="application/rss+xml" />"#,
+    );
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").with_body(xml).create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let app = TestApp::with_config(Config {
+        public_base_url: Some("https://podreplay.example/".to_string()),
+        fetch_allow: vec!["127.0.0.1".to_string()],
+        ..Config::default()
+    })
+    .await;
+
+    let path = format!("/replay?rule=1w&start=2021-10-23T01:09:00Z&uri={mock_uri}");
+    let response = app
+        .get(&path)
+        .header("Host", "attacker.example")
+        .header("X-Forwarded-Proto", "http")
+        .send()
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = response.text().await.unwrap();
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        body.contains(r#"<atom:link href="https://podreplay.example/replay?rule=1w"#),
+        "{body}"
+    );
+    assert!(!body.contains("attacker.example"));
+
+    mock.assert();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn applies_feed_overrides() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").with_body(xml).create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!(
+        "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-11-23T01:09:00Z&title=Book+Club&description=Season+1+rewatch+with+the+book+club&image=https%3A%2F%2Fexample.com%2Fclub.png&author=The+Club&uri={mock_uri}"
+    );
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+    let body = response.bytes().await.unwrap();
+    let text = std::str::from_utf8(&body).unwrap();
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(text.contains("<title>Book Club</title>"));
+    assert!(text.contains("<description>Season 1 rewatch with the book club</description>"));
+    assert!(text.contains(r#"<itunes:image href="https://example.com/club.png"/>"#));
+    assert!(text.contains("<itunes:author>The Club</itunes:author>"));
+    assert!(!text.contains("A weblog about scripting"));
+    assert_namespace_well_formed(&body);
+
+    mock.assert();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn applies_title_template() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").with_body(xml).create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!(
+        "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-11-23T01:09:00Z&title_template=%7Bn%7D%2F%7Btotal%7D+-+%7Btitle%7D+(orig.+%7Boriginal_date%7D)&uri={mock_uri}"
+    );
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+    let body = response.text().await.unwrap();
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains(
+        "<title>1/2 - Joshua Allen: Who loves namespaces? (orig. 2002-09-29)</title>"
+    ));
+    // Untitled items fall back to the start of their description.
+    assert!(body.contains("<title>2/2 - With any luck we should have"));
+    assert!(body.contains("(orig. 2002-09-30)</title>"));
+
+    mock.assert();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn returns_400_for_invalid_title_template() {
+    let app = TestApp::new().await;
+
+    let path = "/replay?rule=1w&start=2021-10-23T01:09:00Z&title_template=%7Bepisode%7D&uri=/doesnotmatter";
+    let response = app.get(path).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[traced_test]
+#[tokio::test]
+async fn adds_status_item() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").with_body(xml).create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!(
+        "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-10-24T01:09:00Z&status=true&uri={mock_uri}"
+    );
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+    let body = response.text().await.unwrap();
+
+    assert_eq!(status, StatusCode::OK);
+    let status_item = body.find("<title>Next episode on Saturday, October 30, 2021</title>");
+    assert!(status_item.is_some());
+    assert!(status_item < body.find("<item>\n\t\t\t<title>Joshua Allen"));
+    assert!(body.contains("<description>1 of 2 episodes replayed."));
+
+    mock.assert();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn adds_original_date_note() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").with_body(xml).create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!(
+        "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-11-23T01:09:00Z&original_date=prepend&uri={mock_uri}"
+    );
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+    let body = response.text().await.unwrap();
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains(
+        "<description>&lt;p&gt;Originally published September 29, 2002&lt;/p&gt;Joshua Allen:"
+    ));
+
+    mock.assert();
+}
+
+#[tokio::test]
+async fn keeps_original_formatting_when_faithful() {
+    let xml = include_str!("../../lib/tests/data/megaphone.xml");
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").with_body(xml).create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!(
+        "/replay?rule=1w&start=2022-01-01T01:09:00Z&now=2022-03-01T01:09:00Z&faithful=true&uri={mock_uri}"
+    );
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+    let body = response.text().await.unwrap();
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains(
+        "<guid isPermaLink=\"false\">\n                <![CDATA[614f5f12-4f9c-11eb-a6af-cb9557e04485]]>\n            </guid>"
+    ));
+    assert!(body.contains("<itunes:name></itunes:name>"));
+
+    mock.assert();
+}
+
+#[tokio::test]
+async fn returns_calendar() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").with_body(xml).create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!(
+        "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-10-25T01:09:00Z&format=ics&uri={mock_uri}"
+    );
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+    let content_type = response.headers().get("content-type").cloned();
+    let body = response.text().await.unwrap();
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type.unwrap(), "text/calendar; charset=utf-8");
+    assert!(body.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(body.contains("X-WR-CALNAME:Scripting News (PodReplay)\r\n"));
+    assert!(body.contains(
+        "DTSTART:20211023T010900Z\r\nSUMMARY:Joshua Allen: Who loves namespaces?\r\n"
+    ));
+    assert!(body.contains("DTSTART:20211030T010900Z\r\n"));
+    assert_eq!(body.matches("STATUS:CONFIRMED").count(), 1);
+    assert_eq!(body.matches("STATUS:TENTATIVE").count(), 1);
+
+    mock.assert();
+}
+
+#[tokio::test]
+async fn returns_plan() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").with_body(xml).create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!(
+        "/replay?rule=1w&start=2021-10-23T01:09:00Z&now=2021-10-25T01:09:00Z&format=json&uri={mock_uri}"
+    );
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+    let content_type = response.headers().get_string("content-type").unwrap();
+    let body = response.bytes().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "application/json");
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "items": [
+                {
+                    "id": "http://scriptingnews.userland.com/backissues/2002/09/29#When:12:59:01PM",
+                    "date": "2021-10-23T01:09:00Z",
+                    "projected": false,
+                },
+                {
+                    "id": "http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM",
+                    "date": "2021-10-30T01:09:00Z",
+                    "projected": true,
+                },
+            ],
+            "next_slot": "2021-10-30T01:09:00Z",
+        })
+    );
+
+    mock.assert();
+}
@@ -0,0 +1,188 @@
+mod helpers;
+
+use helpers::TestApp;
+use hyper::{header, StatusCode};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+
+async fn create_replay(app: &TestApp, definition: serde_json::Value) -> serde_json::Value {
+    let response = app
+        .client
+        .post(app.base_url.join("/replays").unwrap())
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(definition.to_string())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = response.bytes().await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn serves_a_stored_replay() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").with_body(xml).create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let app = TestApp::new().await;
+
+    let created = create_replay(
+        &app,
+        json!({
+            "uri": mock_uri,
+            "rule": "1w",
+            "start": "2021-10-23T01:09:00Z",
+            "title": "Stored Title",
+        }),
+    )
+    .await;
+    let path = created["path"].as_str().unwrap();
+    assert_eq!(path, format!("/r/{}", created["slug"].as_str().unwrap()));
+    assert_eq!(created["edit_token"].as_str().unwrap().len(), 32);
+
+    // Anything else in the query is passed along.
+    let response = app
+        .get(&format!("{path}?now=2021-10-25T01:09:00Z"))
+        .send()
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = response.text().await.unwrap();
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("<title>Stored Title</title>"));
+    assert!(body.contains("<pubDate>Sat, 23 Oct 2021 01:09:00 +0000</pubDate>"));
+
+    mock.assert();
+}
+
+#[tokio::test]
+async fn edits_a_stored_replay() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml");
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/hello").with_body(xml).create();
+    let mock_uri = format!("{}/hello", &server.url());
+
+    let app = TestApp::new().await;
+
+    let definition = json!({
+        "uri": mock_uri,
+        "rule": "1w",
+        "start": "2021-10-23T01:09:00Z",
+    });
+    let created = create_replay(&app, definition).await;
+    let slug = created["slug"].as_str().unwrap();
+    let url = app.base_url.join(&format!("/replays/{slug}")).unwrap();
+    let edited = json!({
+        "uri": mock_uri,
+        "rule": "1d",
+        "start": "2021-10-24T01:09:00Z",
+    });
+
+    let response = app
+        .client
+        .put(url.clone())
+        .header(header::CONTENT_TYPE, "application/json")
+        .bearer_auth("not-the-token")
+        .body(edited.to_string())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = app
+        .client
+        .put(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .bearer_auth(created["edit_token"].as_str().unwrap())
+        .body(edited.to_string())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // The same subscription picks up the new schedule.
+    let response = app
+        .get(&format!("/r/{slug}?format=json&now=2021-10-26T01:09:00Z"))
+        .send()
+        .await
+        .unwrap();
+    let body = response.bytes().await.unwrap();
+    let plan: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(plan["items"][0]["date"], "2021-10-24T01:09:00Z");
+    assert_eq!(plan["items"][1]["date"], "2021-10-25T01:09:00Z");
+
+    mock.assert();
+}
+
+#[tokio::test]
+async fn returns_400_for_an_invalid_definition() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .client
+        .post(app.base_url.join("/replays").unwrap())
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(
+            json!({
+                "uri": "http://example.com/feed",
+                "rule": "1w",
+                "start": "whenever",
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn returns_400_for_an_invalid_rule() {
+    let app = TestApp::new().await;
+    let definition = json!({
+        "uri": "http://example.com/feed",
+        "rule": "1w",
+        "start": "2021-10-23T01:09:00Z",
+    });
+    let created = create_replay(&app, definition).await;
+    let slug = created["slug"].as_str().unwrap();
+    let invalid = json!({
+        "uri": "http://example.com/feed",
+        "rule": "every week",
+        "start": "2021-10-23T01:09:00Z",
+    });
+
+    let response = app
+        .client
+        .post(app.base_url.join("/replays").unwrap())
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(invalid.to_string())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .client
+        .put(app.base_url.join(&format!("/replays/{slug}")).unwrap())
+        .header(header::CONTENT_TYPE, "application/json")
+        .bearer_auth(created["edit_token"].as_str().unwrap())
+        .body(invalid.to_string())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn returns_404_for_an_unknown_slug() {
+    let app = TestApp::new().await;
+
+    let response = app.get("/r/nothing").send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,123 @@
+mod helpers;
+
+use std::time::{Duration, Instant};
+
+use helpers::{replay_path, response, Answer, FakeOrigin, TestApp};
+use hyper::StatusCode;
+use podreplay::config::Config;
+use pretty_assertions::assert_eq;
+
+fn quick_retries() -> Config {
+    Config {
+        fetch_allow: vec!["127.0.0.1".to_string()],
+        fetch_retries: 2,
+        fetch_retry_delay: 10,
+        ..Config::default()
+    }
+}
+
+/// An origin that fails the first `failures` requests with `failure`, and
+/// answers the rest with the feed.
+async fn flaky_origin(failures: usize, failure: Answer) -> FakeOrigin {
+    let xml = include_bytes!("../../lib/tests/data/sample_rss_2.0.xml");
+    let feed = response("200 OK", "", xml);
+    FakeOrigin::new(Duration::ZERO, move |n| {
+        if n < failures {
+            failure.clone()
+        } else {
+            Answer::Raw(feed.clone())
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn retries_dropped_connections() {
+    let origin = flaky_origin(2, Answer::HangUp).await;
+
+    let app = TestApp::with_config(quick_retries()).await;
+    let response = app.get(&replay_path(&origin.uri)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(origin.requests(), 3);
+}
+
+#[tokio::test]
+async fn retries_server_errors() {
+    let failure = response("503 Service Unavailable", "", b"");
+    let origin = flaky_origin(1, Answer::Raw(failure)).await;
+
+    let app = TestApp::with_config(quick_retries()).await;
+    let response = app.get(&replay_path(&origin.uri)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(origin.requests(), 2);
+}
+
+#[tokio::test]
+async fn retries_attempts_that_time_out() {
+    let origin = flaky_origin(1, Answer::Hang).await;
+
+    let app = TestApp::with_config(Config {
+        fetch_read_timeout: 1,
+        ..quick_retries()
+    })
+    .await;
+    let response = app.get(&replay_path(&origin.uri)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(origin.requests(), 2);
+}
+
+#[tokio::test]
+async fn waits_as_long_as_asked_to() {
+    let failure = response("429 Too Many Requests", "Retry-After: 1\r\n", b"");
+    let origin = flaky_origin(1, Answer::Raw(failure)).await;
+
+    let app = TestApp::with_config(quick_retries()).await;
+    let started = Instant::now();
+    let response = app.get(&replay_path(&origin.uri)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert_eq!(origin.requests(), 2);
+}
+
+#[tokio::test]
+async fn does_not_wait_longer_than_the_limit() {
+    let failure = response("503 Service Unavailable", "Retry-After: 3600\r\n", b"");
+    let origin = flaky_origin(1, Answer::Raw(failure)).await;
+
+    let app = TestApp::with_config(quick_retries()).await;
+    let response = app.get(&replay_path(&origin.uri)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(origin.requests(), 1);
+}
+
+#[tokio::test]
+async fn gives_up_after_the_last_retry() {
+    let failure = response("500 Internal Server Error", "", b"");
+    let origin = flaky_origin(usize::MAX, Answer::Raw(failure)).await;
+
+    let app = TestApp::new().await;
+    let response = app.get(&replay_path(&origin.uri)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(
+        origin.requests(),
+        Config::default().fetch_retries as usize + 1
+    );
+}
+
+#[tokio::test]
+async fn does_not_retry_other_errors() {
+    let failure = response("404 Not Found", "", b"");
+    let origin = flaky_origin(1, Answer::Raw(failure)).await;
+
+    let app = TestApp::with_config(quick_retries()).await;
+    let response = app.get(&replay_path(&origin.uri)).send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(origin.requests(), 1);
+}
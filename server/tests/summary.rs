@@ -67,7 +67,8 @@ async fn follows_link_meta_in_html() {
             "#
         ))
         .create();
-    let mock_xml = server.mock("GET", "/hello.xml")
+    let mock_xml = server
+        .mock("GET", "/hello.xml")
         .with_body(include_str!("../../lib/tests/data/sample_rss_2.0.xml"))
         .create();
 
@@ -101,3 +102,87 @@ async fn follows_link_meta_in_html() {
     mock_html.assert();
     mock_xml.assert();
 }
+
+#[traced_test]
+#[tokio::test]
+async fn returns_problem_details_for_broken_feeds() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml").replace(
+        "<guid>http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM</guid>",
+        "<guid>http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM</gui>",
+    );
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/broken").with_body(xml).create();
+    let mock_uri = format!("{}/broken", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!("/summary?uri={mock_uri}");
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+    let content_type = response.headers().get("content-type").cloned().unwrap();
+
+    let body = response.bytes().await.unwrap();
+    let actual: serde_json::Value = from_slice(&body).unwrap();
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(content_type, "application/problem+json");
+    assert_eq!(actual["line"], 26);
+    assert_eq!(actual["path"], "rss/channel/item[1]/guid");
+
+    mock.assert();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn skips_broken_items_when_lenient() {
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml").replace(
+        "<guid>http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM</guid>",
+        "<guid>http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM</gui>",
+    );
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/broken").with_body(xml).create();
+    let mock_uri = format!("{}/broken", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!("/summary?lenient=true&uri={mock_uri}");
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+
+    let body = response.bytes().await.unwrap();
+    let actual: serde_json::Value = from_slice(&body).unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(actual["items"].as_array().unwrap().len(), 1);
+    assert_eq!(actual["repairs"][0]["kind"], "skipped_item");
+    assert_eq!(actual["repairs"][0]["line"], 26);
+
+    mock.assert();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn does_not_repair_huge_feeds() {
+    let padding = format!("<!-- {} -->\n</channel>", "x".repeat(9 * 1024 * 1024));
+    let xml = include_str!("../../lib/tests/data/sample_rss_2.0.xml")
+        .replace(
+            "<guid>http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM</guid>",
+            "<guid>http://scriptingnews.userland.com/backissues/2002/09/29#When:6:56:02PM</gui>",
+        )
+        .replace("</channel>", &padding);
+    let mut server = mockito::Server::new();
+    let mock = server.mock("GET", "/broken").with_body(xml).create();
+    let mock_uri = format!("{}/broken", &server.url());
+
+    let app = TestApp::new().await;
+
+    let path = format!("/summary?lenient=true&uri={mock_uri}");
+    let response = app.get(&path).send().await.unwrap();
+    let status = response.status();
+
+    let body = response.bytes().await.unwrap();
+    let actual: serde_json::Value = from_slice(&body).unwrap();
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(actual["line"], 26);
+    assert_eq!(actual["path"], "rss/channel/item[1]/guid");
+
+    mock.assert();
+}